@@ -0,0 +1,131 @@
+use rusqlite::DatabaseName;
+use tokio::sync::{mpsc, oneshot};
+
+use super::watchdog::Watchdog;
+use super::Error;
+
+enum BlobCommand {
+    Size {
+        tx: oneshot::Sender<Result<usize, Error>>,
+    },
+    Read {
+        offset: usize,
+        len: usize,
+        tx: oneshot::Sender<Result<Vec<u8>, Error>>,
+    },
+    Write {
+        offset: usize,
+        data: Vec<u8>,
+        tx: oneshot::Sender<Result<(), Error>>,
+    },
+}
+
+pub(super) struct BlobHandle {
+    tx: mpsc::Sender<BlobCommand>,
+    watchdog: Watchdog,
+}
+
+impl BlobHandle {
+    pub async fn size(&mut self) -> Result<usize, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.request(BlobCommand::Size { tx }, rx).await
+    }
+
+    pub async fn read(&mut self, offset: usize, len: usize) -> Result<Vec<u8>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.request(BlobCommand::Read { offset, len, tx }, rx)
+            .await
+    }
+
+    pub async fn write(&mut self, offset: usize, data: Vec<u8>) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.request(BlobCommand::Write { offset, data, tx }, rx)
+            .await
+    }
+
+    /// Sends the command to the worker and waits for the result.
+    async fn request<T>(
+        &mut self,
+        cmd: BlobCommand,
+        rx: oneshot::Receiver<Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let tx = &self.tx;
+        self.watchdog
+            .watch(async move {
+                tx.send(cmd).await.map_err(|_| Error::WorkerClosed)?;
+                rx.await.map_err(|_| Error::WorkerClosed)?
+            })
+            .await
+    }
+}
+
+/// Location of the blob to open.
+pub(super) struct BlobLocation {
+    pub db: String,
+    pub table: String,
+    pub column: String,
+    pub rowid: i64,
+    pub read_only: bool,
+}
+
+pub(super) struct BlobTask {
+    location: BlobLocation,
+    watchdog: Watchdog,
+}
+
+impl BlobTask {
+    pub fn new(location: BlobLocation, watchdog: Watchdog) -> Self {
+        Self { location, watchdog }
+    }
+
+    /// Serves commands of the blob until its handle is dropped.
+    pub fn blocking_run(
+        self,
+        conn: &rusqlite::Connection,
+        handle_rx: oneshot::Sender<Result<BlobHandle, Error>>,
+    ) {
+        let location = &self.location;
+        let mut blob = match conn.blob_open(
+            DatabaseName::Attached(&location.db),
+            &location.table,
+            &location.column,
+            location.rowid,
+            location.read_only,
+        ) {
+            Ok(blob) => blob,
+            Err(err) => {
+                let _ = handle_rx.send(Err(err.into()));
+                return;
+            }
+        };
+        let (tx, mut rx) = mpsc::channel(1);
+        let handle = BlobHandle {
+            tx,
+            watchdog: self.watchdog,
+        };
+        if handle_rx.send(Ok(handle)).is_err() {
+            // Drop blob if nobody listens result.
+            return;
+        }
+        while let Some(cmd) = rx.blocking_recv() {
+            match cmd {
+                BlobCommand::Size { tx } => {
+                    let _ = tx.send(Ok(blob.len()));
+                }
+                BlobCommand::Read { offset, len, tx } => {
+                    // Reads past the end of the blob are short.
+                    let mut data = vec![0; len.min(blob.len().saturating_sub(offset))];
+                    let result = if data.is_empty() {
+                        Ok(())
+                    } else {
+                        blob.read_at_exact(&mut data, offset)
+                    };
+                    let _ = tx.send(result.map(|_| data).map_err(Error::from));
+                }
+                BlobCommand::Write { offset, data, tx } => {
+                    let _ = tx.send(blob.write_at(&data, offset).map_err(Error::from));
+                }
+            }
+        }
+    }
+}
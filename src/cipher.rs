@@ -0,0 +1,57 @@
+use std::fmt;
+
+use zeroize::Zeroizing;
+
+use super::Error;
+
+/// A key of a database encrypted by SQLCipher.
+///
+/// The key material is zeroized when the last copy is dropped.
+#[derive(Clone)]
+pub struct SecretKey {
+    /// The key formatted as the SQL literal of `PRAGMA key`.
+    literal: Zeroizing<String>,
+}
+
+impl SecretKey {
+    /// Creates a key derived by SQLCipher from the passphrase.
+    pub fn passphrase<S: Into<String>>(passphrase: S) -> Self {
+        let passphrase = Zeroizing::new(passphrase.into());
+        let mut literal = Zeroizing::new(String::with_capacity(passphrase.len() + 2));
+        literal.push('\'');
+        for c in passphrase.chars() {
+            if c == '\'' {
+                literal.push('\'');
+            }
+            literal.push(c);
+        }
+        literal.push('\'');
+        Self { literal }
+    }
+
+    /// Creates a raw 256-bit key used by SQLCipher without derivation.
+    pub fn raw(key: &[u8; 32]) -> Self {
+        const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+        let mut literal = Zeroizing::new(String::with_capacity(key.len() * 2 + 5));
+        literal.push_str("\"x'");
+        for byte in key {
+            literal.push(DIGITS[(byte >> 4) as usize] as char);
+            literal.push(DIGITS[(byte & 0xf) as usize] as char);
+        }
+        literal.push_str("'\"");
+        Self { literal }
+    }
+
+    /// Runs the pragma with the key, for example `key` or `rekey`.
+    pub(super) fn apply(&self, conn: &rusqlite::Connection, pragma: &str) -> Result<(), Error> {
+        let statement = Zeroizing::new(format!("PRAGMA {pragma} = {}", self.literal.as_str()));
+        conn.execute_batch(&statement)?;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretKey(..)")
+    }
+}
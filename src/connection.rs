@@ -1,129 +1,441 @@
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use rusqlite::params_from_iter;
+use rusqlite::TransactionBehavior;
 use tokio::sync::{mpsc, oneshot};
 
 use crate::Error;
 
-use super::query::{ExecuteCommand, QueryCommand, QueryHandle, QueryTask};
+use super::blob::{BlobHandle, BlobLocation, BlobTask};
+#[cfg(feature = "hooks")]
+use super::hooks::{WalHook, WalSender};
+use super::query::{blocking_query, execute, Arguments, ExecuteCommand, QueryCommand, QueryHandle};
 use super::transaction::{TransactionHandle, TransactionTask};
-use super::{Status, Value};
+use super::watchdog::Watchdog;
+use super::{ConnectionOptions, Status};
+
+type CallFn = Box<dyn FnOnce(&mut rusqlite::Connection) + Send>;
 
 enum ConnectionCommand {
     Transaction {
+        behavior: TransactionBehavior,
         tx: oneshot::Sender<Result<TransactionHandle, Error>>,
     },
     Execute(ExecuteCommand),
     Query(QueryCommand),
-    Shutdown,
+    Batch {
+        statement: String,
+        tx: oneshot::Sender<Result<(), Error>>,
+    },
+    Call {
+        f: CallFn,
+        /// Closed once the caller stops waiting, if anybody waits at all.
+        waiter: Option<oneshot::Sender<()>>,
+    },
+    #[cfg(feature = "hooks")]
+    WalHook {
+        sender: WalSender,
+        tx: oneshot::Sender<Result<(), Error>>,
+    },
+}
+
+impl ConnectionCommand {
+    /// Returns true if nobody waits for the result of the command.
+    fn is_abandoned(&self) -> bool {
+        match self {
+            ConnectionCommand::Transaction { tx, .. } => tx.is_closed(),
+            ConnectionCommand::Execute(cmd) => cmd.tx.is_closed(),
+            ConnectionCommand::Query(cmd) => cmd.tx.is_closed(),
+            ConnectionCommand::Batch { tx, .. } => tx.is_closed(),
+            ConnectionCommand::Call { waiter, .. } => waiter.as_ref().is_none_or(|v| v.is_closed()),
+            #[cfg(feature = "hooks")]
+            ConnectionCommand::WalHook { tx, .. } => tx.is_closed(),
+        }
+    }
+}
+
+/// Numbers of commands received and lost by the worker.
+#[derive(Clone, Default)]
+pub(super) struct CommandCounters {
+    received: Arc<AtomicU64>,
+    lost: Arc<AtomicU64>,
+}
+
+impl CommandCounters {
+    /// Returns the number of commands left unprocessed when the worker
+    /// exited, which nobody waited for.
+    pub fn lost(&self) -> u64 {
+        self.lost.load(Ordering::Relaxed)
+    }
+}
+
+/// The total number of rows changed by the connection.
+///
+/// The worker updates it before replying, so the changes made by a command
+/// are visible once its result is received.
+#[derive(Clone, Default)]
+pub(super) struct ChangeCounter(Arc<AtomicU64>);
+
+impl ChangeCounter {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Acquire)
+    }
+
+    pub fn update(&self, conn: &rusqlite::Connection) {
+        let changes = unsafe { rusqlite::ffi::sqlite3_total_changes(conn.handle()) };
+        self.0.store(changes as u64, Ordering::Release);
+    }
+}
+
+/// The queue of commands received by the worker.
+///
+/// Commands left in the queue are dropped once the worker exits, even by a
+/// panic. Their callers see the worker closed, while the commands nobody
+/// waits for are counted as lost.
+struct CommandQueue {
+    rx: mpsc::Receiver<ConnectionCommand>,
+    counters: CommandCounters,
 }
 
-pub(super) struct ConnectionHandle(mpsc::Sender<ConnectionCommand>);
+impl CommandQueue {
+    fn blocking_recv(&mut self) -> Option<ConnectionCommand> {
+        let cmd = self.rx.blocking_recv()?;
+        self.counters.received.fetch_add(1, Ordering::Relaxed);
+        Some(cmd)
+    }
+}
+
+impl Drop for CommandQueue {
+    fn drop(&mut self) {
+        self.rx.close();
+        while let Ok(cmd) = self.rx.try_recv() {
+            if cmd.is_abandoned() {
+                self.counters.lost.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+pub(super) struct ConnectionHandle {
+    tx: mpsc::Sender<ConnectionCommand>,
+    counters: CommandCounters,
+    changes: ChangeCounter,
+    interrupt: Arc<rusqlite::InterruptHandle>,
+    watchdog: Watchdog,
+}
 
 impl ConnectionHandle {
-    pub async fn transaction(&mut self) -> Result<TransactionHandle, Error> {
+    pub fn is_poisoned(&self) -> bool {
+        self.watchdog.is_poisoned()
+    }
+
+    /// Returns true if the worker has stopped or stopped responding.
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed() || self.is_poisoned()
+    }
+
+    pub fn counters(&self) -> CommandCounters {
+        self.counters.clone()
+    }
+
+    pub fn changes(&self) -> u64 {
+        self.changes.get()
+    }
+
+    pub fn interrupt_handle(&self) -> Arc<rusqlite::InterruptHandle> {
+        self.interrupt.clone()
+    }
+
+    pub async fn transaction(
+        &mut self,
+        behavior: TransactionBehavior,
+    ) -> Result<TransactionHandle, Error> {
         let (tx, rx) = oneshot::channel();
-        self.0
-            .send(ConnectionCommand::Transaction { tx })
+        self.request(ConnectionCommand::Transaction { behavior, tx }, rx)
             .await
-            .map_err(|_| Error::InvalidQuery)?;
-        rx.await.unwrap()
     }
 
     pub async fn execute(
         &mut self,
         statement: String,
-        arguments: Vec<Value>,
+        arguments: Arguments,
     ) -> Result<Status, Error> {
         let (tx, rx) = oneshot::channel();
-        self.0
-            .send(ConnectionCommand::Execute(ExecuteCommand {
+        self.request(
+            ConnectionCommand::Execute(ExecuteCommand {
                 statement,
                 arguments,
+                enqueued: Instant::now(),
                 tx,
-            }))
+            }),
+            rx,
+        )
+        .await
+    }
+
+    pub async fn execute_batch(&mut self, statement: String) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.request(ConnectionCommand::Batch { statement, tx }, rx)
             .await
-            .map_err(|_| Error::InvalidQuery)?;
-        rx.await.unwrap()
     }
 
     pub async fn query(
         &mut self,
         statement: String,
-        arguments: Vec<Value>,
+        arguments: Arguments,
+        capacity: usize,
     ) -> Result<QueryHandle, Error> {
         let (tx, rx) = oneshot::channel();
-        self.0
-            .send(ConnectionCommand::Query(QueryCommand {
+        self.request(
+            ConnectionCommand::Query(QueryCommand {
                 statement,
                 arguments,
+                capacity,
+                enqueued: Instant::now(),
                 tx,
-            }))
+            }),
+            rx,
+        )
+        .await
+    }
+
+    pub async fn call<F, T>(&mut self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut rusqlite::Connection) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let changes = self.changes.clone();
+        let f = move |conn: &mut rusqlite::Connection| {
+            let result = f(conn);
+            changes.update(conn);
+            let _ = tx.send(result);
+        };
+        let (waiter, _waiting) = oneshot::channel();
+        let cmd = ConnectionCommand::Call {
+            f: Box::new(f),
+            waiter: Some(waiter),
+        };
+        self.request(cmd, rx).await
+    }
+
+    /// Schedules the closure on the worker without waiting for its completion.
+    pub async fn spawn<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut rusqlite::Connection) + Send + 'static,
+    {
+        let tx = &self.tx;
+        let cmd = ConnectionCommand::Call {
+            f: Box::new(f),
+            waiter: None,
+        };
+        self.watchdog
+            .watch(async move {
+                tx.send(cmd).await.map_err(|_| Error::WorkerClosed)?;
+                Ok(())
+            })
             .await
-            .map_err(|_| Error::InvalidQuery)?;
-        rx.await.unwrap()
     }
-}
 
-impl Drop for ConnectionHandle {
-    fn drop(&mut self) {
-        let _ = tokio::task::block_in_place(|| {
-            tokio::runtime::Handle::current().block_on(self.0.send(ConnectionCommand::Shutdown))
+    #[cfg(feature = "hooks")]
+    pub async fn wal_hook(&mut self, sender: WalSender) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.request(ConnectionCommand::WalHook { sender, tx }, rx)
+            .await
+    }
+
+    /// Opens the blob on the worker, which serves it until the handle is dropped.
+    pub async fn blob_open(&mut self, location: BlobLocation) -> Result<BlobHandle, Error> {
+        let (tx, rx) = oneshot::channel();
+        let task = BlobTask::new(location, self.watchdog.clone());
+        self.spawn(move |conn| task.blocking_run(conn, tx)).await?;
+        self.watchdog
+            .watch(async move { rx.await.map_err(|_| Error::WorkerClosed)? })
+            .await
+    }
+
+    /// Spawns a task that checkpoints the WAL once the worker has not
+    /// received commands for the idle duration.
+    ///
+    /// The task stops when the connection is closed.
+    pub fn spawn_idle_checkpoint(&self, idle: Duration) {
+        let tx = self.tx.downgrade();
+        let counters = self.counters.clone();
+        tokio::spawn(async move {
+            let mut seen = counters.received.load(Ordering::Relaxed);
+            let mut checkpointed = true;
+            loop {
+                tokio::time::sleep(idle).await;
+                let Some(tx) = tx.upgrade() else {
+                    return;
+                };
+                let current = counters.received.load(Ordering::Relaxed);
+                if current != seen {
+                    seen = current;
+                    checkpointed = false;
+                    continue;
+                }
+                if checkpointed {
+                    continue;
+                }
+                let f = |conn: &mut rusqlite::Connection| {
+                    let _ = conn.query_row("PRAGMA wal_checkpoint(PASSIVE)", [], |_| Ok(()));
+                };
+                // Skip the checkpoint if the worker is busy with other commands.
+                let cmd = ConnectionCommand::Call {
+                    f: Box::new(f),
+                    waiter: None,
+                };
+                if tx.try_send(cmd).is_ok() {
+                    seen += 1;
+                    checkpointed = true;
+                }
+            }
         });
     }
+
+    /// Sends the command to the worker and waits for the result.
+    async fn request<T>(
+        &mut self,
+        cmd: ConnectionCommand,
+        rx: oneshot::Receiver<Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let tx = &self.tx;
+        self.watchdog
+            .watch(async move {
+                tx.send(cmd).await.map_err(|_| Error::WorkerClosed)?;
+                rx.await.map_err(|_| Error::WorkerClosed)?
+            })
+            .await
+    }
 }
 
 pub(super) struct ConnectionTask {
     path: PathBuf,
+    options: ConnectionOptions,
+    watchdog: Watchdog,
 }
 
 impl ConnectionTask {
-    pub fn new(path: PathBuf) -> Self {
-        Self { path }
+    pub fn new(path: PathBuf, options: ConnectionOptions, watchdog: Watchdog) -> Self {
+        Self {
+            path,
+            options,
+            watchdog,
+        }
+    }
+
+    fn open(&self) -> Result<rusqlite::Connection, Error> {
+        let conn = rusqlite::Connection::open_with_flags(&self.path, self.options.flags)?;
+        self.options.apply(&conn)?;
+        Ok(conn)
     }
 
-    pub fn blocking_run(self, handle_rx: oneshot::Sender<Result<ConnectionHandle, Error>>) {
-        let mut conn = match rusqlite::Connection::open(self.path) {
+    /// Launches the worker using the spawner from the options.
+    pub fn spawn(
+        self,
+        handle_rx: oneshot::Sender<Result<ConnectionHandle, Error>>,
+    ) -> WorkerHandle {
+        let Some(spawner) = self.options.spawner.clone() else {
+            return WorkerHandle::Tokio(tokio::task::spawn_blocking(|| {
+                self.blocking_run(handle_rx)
+            }));
+        };
+        let (done_tx, done_rx) = oneshot::channel();
+        (spawner.0)(Box::new(move || {
+            self.blocking_run(handle_rx);
+            let _ = done_tx.send(());
+        }));
+        WorkerHandle::Custom(done_rx)
+    }
+
+    fn blocking_run(self, handle_rx: oneshot::Sender<Result<ConnectionHandle, Error>>) {
+        let mut conn = match self.open() {
             Ok(v) => v,
             Err(err) => {
                 let _ = handle_rx.send(Err(err));
                 return;
             }
         };
-        let (tx, mut rx) = mpsc::channel(1);
-        if let Err(_) = handle_rx.send(Ok(ConnectionHandle(tx))) {
+        let (tx, rx) = mpsc::channel(1);
+        let counters = CommandCounters::default();
+        let changes = ChangeCounter::default();
+        let handle = ConnectionHandle {
+            tx,
+            counters: counters.clone(),
+            changes: changes.clone(),
+            interrupt: Arc::new(conn.get_interrupt_handle()),
+            watchdog: self.watchdog.clone(),
+        };
+        if handle_rx.send(Ok(handle)).is_err() {
             // Drop connection if nobody listens result.
             return;
         }
-        while let Some(cmd) = rx.blocking_recv() {
+        // Declared after the connection to be dropped before it.
+        #[cfg(feature = "hooks")]
+        let mut wal_hook: Option<WalHook> = None;
+        let mut queue = CommandQueue { rx, counters };
+        while let Some(cmd) = queue.blocking_recv() {
             match cmd {
-                ConnectionCommand::Transaction { tx, .. } => {
-                    let task = TransactionTask::new(&mut conn);
+                ConnectionCommand::Transaction { behavior, tx } => {
+                    let task = TransactionTask::new(
+                        &mut conn,
+                        behavior,
+                        changes.clone(),
+                        self.watchdog.clone(),
+                    );
                     task.blocking_run(tx);
                     continue;
                 }
                 ConnectionCommand::Execute(cmd) => {
-                    let _ = cmd.tx.send(
-                        conn.execute(&cmd.statement, params_from_iter(cmd.arguments.into_iter()))
-                            .map(|rows_affected| Status {
-                                rows_affected,
-                                last_insert_id: Some(conn.last_insert_rowid()),
-                            }),
-                    );
+                    let status = execute(&conn, &cmd);
+                    changes.update(&conn);
+                    let _ = cmd.tx.send(status);
+                }
+                ConnectionCommand::Batch { statement, tx } => {
+                    let result = conn.execute_batch(&statement).map_err(Error::from);
+                    changes.update(&conn);
+                    let _ = tx.send(result);
                 }
                 ConnectionCommand::Query(cmd) => {
-                    let stmt = match conn.prepare(&cmd.statement) {
-                        Ok(stmt) => stmt,
-                        Err(err) => {
-                            let _ = cmd.tx.send(Err(err));
-                            continue;
-                        }
-                    };
-                    let task = QueryTask::new(stmt, cmd.arguments);
-                    task.blocking_run(cmd.tx);
+                    blocking_query(&conn, cmd, &changes, self.watchdog.clone());
+                }
+                ConnectionCommand::Call { f, .. } => f(&mut conn),
+                #[cfg(feature = "hooks")]
+                ConnectionCommand::WalHook { sender, tx } => {
+                    // The replaced hook is removed before the new one is installed.
+                    drop(wal_hook.take());
+                    wal_hook = Some(WalHook::install(&conn, sender));
+                    let _ = tx.send(Ok(()));
                 }
-                ConnectionCommand::Shutdown => return,
             }
         }
     }
 }
+
+/// A handle to wait for the connection worker.
+pub(super) enum WorkerHandle {
+    Tokio(tokio::task::JoinHandle<()>),
+    /// The worker launched by a custom spawner, which signals on completion.
+    Custom(oneshot::Receiver<()>),
+}
+
+impl WorkerHandle {
+    /// Waits for the worker to finish.
+    pub async fn join(self) -> Result<(), Error> {
+        match self {
+            WorkerHandle::Tokio(handle) => handle.await.map_err(Error::Join),
+            // The signal is lost only if the worker panicked.
+            WorkerHandle::Custom(rx) => rx.await.map_err(|_| Error::WorkerClosed),
+        }
+    }
+
+    /// Stops waiting for the worker, letting it finish in background.
+    pub fn abort(self) {
+        if let WorkerHandle::Tokio(handle) = self {
+            handle.abort();
+        }
+    }
+}
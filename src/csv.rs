@@ -0,0 +1,67 @@
+use super::Value;
+
+/// Options used to export query rows as CSV.
+#[derive(Clone, Debug)]
+pub struct CsvOptions {
+    pub(super) header: bool,
+    pub(super) delimiter: char,
+}
+
+impl CsvOptions {
+    pub fn new() -> Self {
+        Self {
+            header: true,
+            delimiter: ',',
+        }
+    }
+
+    /// Enables or disables the header row with column names.
+    pub fn header(mut self, enabled: bool) -> Self {
+        self.header = enabled;
+        self
+    }
+
+    /// Sets the character separating fields of a record.
+    pub fn delimiter(mut self, delimiter: char) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Formats the fields as a CSV record terminated by a line break.
+    pub(super) fn record<'a>(&self, fields: impl IntoIterator<Item = &'a str>) -> String {
+        let mut record = String::new();
+        for (i, field) in fields.into_iter().enumerate() {
+            if i > 0 {
+                record.push(self.delimiter);
+            }
+            if field.contains([self.delimiter, '"', '\n', '\r']) {
+                record.push('"');
+                record.push_str(&field.replace('"', "\"\""));
+                record.push('"');
+            } else {
+                record.push_str(field);
+            }
+        }
+        record.push_str("\r\n");
+        record
+    }
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Formats the value as a CSV field.
+///
+/// `NULL` is written as an empty field and blobs as hexadecimal strings.
+pub(super) fn csv_field(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Integer(v) => v.to_string(),
+        Value::Real(v) => v.to_string(),
+        Value::Text(v) => v.clone(),
+        Value::Blob(v) => v.iter().map(|byte| format!("{byte:02X}")).collect(),
+    }
+}
@@ -0,0 +1,130 @@
+use std::ffi::CString;
+
+use rusqlite::types::ValueRef;
+use tokio::sync::mpsc;
+
+use super::sqlite::quote_identifier;
+use super::Error;
+
+/// Sends SQL statements reconstructing the database until the receiver is closed.
+///
+/// All tables are read inside one savepoint to see a consistent snapshot.
+pub(super) fn blocking_dump(conn: &rusqlite::Connection, tx: mpsc::Sender<Result<String, Error>>) {
+    let result = conn
+        .execute_batch("SAVEPOINT tokio_sqlite_dump")
+        .map_err(Error::from)
+        .and_then(|_| {
+            let result = dump(conn, &tx);
+            let release = conn.execute_batch("RELEASE tokio_sqlite_dump");
+            result.and(release.map_err(Error::from))
+        });
+    if let Err(err) = result {
+        let _ = tx.blocking_send(Err(err));
+    }
+}
+
+fn dump(
+    conn: &rusqlite::Connection,
+    tx: &mpsc::Sender<Result<String, Error>>,
+) -> Result<(), Error> {
+    let send = |statement: String| tx.blocking_send(Ok(statement)).is_ok();
+    if !send("PRAGMA foreign_keys=OFF;".to_owned()) || !send("BEGIN TRANSACTION;".to_owned()) {
+        return Ok(());
+    }
+    let mut stmt = conn.prepare(
+        "SELECT name, sql FROM sqlite_master \
+        WHERE type = 'table' AND sql NOT NULL \
+        AND (name NOT LIKE 'sqlite_%' OR name = 'sqlite_sequence') \
+        ORDER BY rowid",
+    )?;
+    let tables = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (name, sql) in tables {
+        let statement = if name == "sqlite_sequence" {
+            "DELETE FROM sqlite_sequence;".to_owned()
+        } else {
+            format!("{sql};")
+        };
+        if !send(statement) {
+            return Ok(());
+        }
+        let table = quote_identifier(&name);
+        // Hidden and generated columns can not be inserted.
+        let columns = conn
+            .prepare("SELECT name FROM pragma_table_xinfo(?1) WHERE hidden = 0")?
+            .query_map([&name], |row| row.get::<_, String>(0))?
+            .map(|column| column.map(|column| quote_identifier(&column)))
+            .collect::<Result<Vec<_>, _>>()?
+            .join(",");
+        let mut stmt = conn.prepare(&format!("SELECT {columns} FROM {table}"))?;
+        let columns_len = stmt.column_count();
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let mut values = Vec::with_capacity(columns_len);
+            for i in 0..columns_len {
+                values.push(value_literal(row.get_ref(i)?));
+            }
+            let values = values.join(",");
+            if !send(format!("INSERT INTO {table}({columns}) VALUES({values});")) {
+                return Ok(());
+            }
+        }
+    }
+    let mut stmt = conn.prepare(
+        "SELECT sql FROM sqlite_master \
+        WHERE type IN ('index', 'trigger', 'view') AND sql NOT NULL \
+        ORDER BY rowid",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        if !send(format!("{};", row.get::<_, String>(0)?)) {
+            return Ok(());
+        }
+    }
+    send("COMMIT;".to_owned());
+    Ok(())
+}
+
+/// Returns true if the SQL text ends with a complete statement.
+pub(super) fn is_complete_statement(sql: &str) -> bool {
+    match CString::new(sql) {
+        Ok(sql) => unsafe { rusqlite::ffi::sqlite3_complete(sql.as_ptr()) != 0 },
+        Err(_) => true,
+    }
+}
+
+/// Returns true if the statement begins, commits or rolls back a transaction.
+pub(super) fn is_transaction_statement(statement: &str) -> bool {
+    let keyword = statement
+        .split(|c: char| !c.is_ascii_alphabetic())
+        .next()
+        .unwrap_or_default()
+        .to_ascii_uppercase();
+    matches!(keyword.as_str(), "BEGIN" | "COMMIT" | "END" | "ROLLBACK")
+}
+
+/// Formats the value as an SQL literal.
+fn value_literal(value: ValueRef) -> String {
+    match value {
+        ValueRef::Null => "NULL".to_owned(),
+        ValueRef::Integer(v) => v.to_string(),
+        ValueRef::Real(v) if v.is_nan() => "NULL".to_owned(),
+        ValueRef::Real(v) if v.is_infinite() => {
+            if v > 0.0 { "9.0e999" } else { "-9.0e999" }.to_owned()
+        }
+        ValueRef::Real(v) => format!("{v:?}"),
+        ValueRef::Text(v) => format!("'{}'", String::from_utf8_lossy(v).replace('\'', "''")),
+        ValueRef::Blob(v) => {
+            let mut literal = String::with_capacity(v.len() * 2 + 3);
+            literal.push_str("X'");
+            for byte in v {
+                literal.push_str(&format!("{byte:02X}"));
+            }
+            literal.push('\'');
+            literal
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::fmt;
+
+use rusqlite::ErrorCode;
+
+use super::JournalMode;
+
+/// An error returned by the asynchronous SQLite client.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// An error reported by SQLite.
+    Sqlite(rusqlite::Error),
+    /// An I/O error.
+    Io(std::io::Error),
+    /// SQLite kept another journal mode than the requested one.
+    JournalMode {
+        requested: JournalMode,
+        applied: String,
+    },
+    /// No statement is registered on the [`Pool`](crate::Pool) under the
+    /// name.
+    UnknownStatement { name: String },
+    /// A bound text or blob value exceeds the length limit of the connection.
+    ValueTooLarge { size: usize, limit: usize },
+    /// A transaction is already open on the connection.
+    ///
+    /// Use savepoints to nest transactions.
+    TransactionAlreadyOpen,
+    /// Columns of bulk arguments have different numbers of values.
+    ColumnLengthMismatch { expected: usize, actual: usize },
+    /// Executing the statement with one of the argument sets failed.
+    ArgumentSet { index: usize, source: Box<Error> },
+    /// The value of the struct field could not be read from the row.
+    Field {
+        name: &'static str,
+        source: Box<Error>,
+    },
+    /// A query expected to return at most one row returned more.
+    MultipleRows,
+    /// A function with the same name and number of arguments is already
+    /// registered on the connection.
+    FunctionAlreadyExists,
+    /// The query passed to
+    /// [`Connection::query_page`](crate::Connection::query_page) already
+    /// contains a `LIMIT` clause.
+    LimitConflict,
+    /// The query did not complete within the timeout and was interrupted.
+    Timeout,
+    /// The query returned more rows or bytes than allowed.
+    ResultTooLarge { max_rows: usize, max_bytes: usize },
+    /// The database cannot be rekeyed because it was opened without a key.
+    #[cfg(feature = "sqlcipher")]
+    NotEncrypted,
+    /// The closure passed to [`Connection::call`](crate::Connection::call)
+    /// panicked with the message.
+    Panic { message: String },
+    /// The connection worker has stopped and cannot process commands.
+    WorkerClosed,
+    /// The connection worker exited without processing commands that nobody
+    /// waited for.
+    CommandsLost { count: u64 },
+    /// The connection worker did not respond within the watchdog timeout.
+    Poisoned,
+    /// The connection worker panicked or was cancelled.
+    Join(tokio::task::JoinError),
+}
+
+impl Error {
+    /// Returns true if the database is busy or locked by another connection.
+    pub fn is_busy(&self) -> bool {
+        match self {
+            Error::Sqlite(err) => matches!(
+                err.sqlite_error_code(),
+                Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+            ),
+            _ => false,
+        }
+    }
+
+    /// Returns true if a constraint such as `UNIQUE`, `NOT NULL` or a
+    /// foreign key was violated.
+    ///
+    /// Use [`Error::extended_code`] to tell the constraints apart.
+    pub fn is_constraint_violation(&self) -> bool {
+        match self {
+            Error::Sqlite(err) => err.sqlite_error_code() == Some(ErrorCode::ConstraintViolation),
+            _ => false,
+        }
+    }
+
+    /// Returns the name of the missing table if the statement refers to a
+    /// table that does not exist, for example one dropped or renamed by
+    /// another connection.
+    ///
+    /// The name is taken from the SQLite message and may be qualified with
+    /// the schema, as in `main.users`.
+    pub fn no_such_table(&self) -> Option<&str> {
+        match self {
+            Error::Sqlite(rusqlite::Error::SqliteFailure(_, Some(message))) => {
+                message.strip_prefix("no such table: ")
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the extended result code reported by SQLite, for example
+    /// `SQLITE_CONSTRAINT_PRIMARYKEY`.
+    pub fn extended_code(&self) -> Option<i32> {
+        match self {
+            Error::Sqlite(rusqlite::Error::SqliteFailure(err, _)) => Some(err.extended_code),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Sqlite(err) => err.fmt(f),
+            Error::Io(err) => err.fmt(f),
+            Error::JournalMode { requested, applied } => write!(
+                f,
+                "journal mode {} was requested but {applied} is used",
+                requested.as_str()
+            ),
+            Error::UnknownStatement { name } => write!(f, "unknown statement `{name}`"),
+            Error::ValueTooLarge { size, limit } => write!(
+                f,
+                "value of {size} bytes exceeds the length limit of {limit} bytes"
+            ),
+            Error::TransactionAlreadyOpen => {
+                f.write_str("transaction is already open, use savepoints instead")
+            }
+            Error::ColumnLengthMismatch { expected, actual } => write!(
+                f,
+                "column has {actual} values while {expected} are expected"
+            ),
+            Error::ArgumentSet { index, source } => write!(f, "argument set {index}: {source}"),
+            Error::Field { name, source } => write!(f, "field `{name}`: {source}"),
+            Error::MultipleRows => f.write_str("query returned more than one row"),
+            Error::FunctionAlreadyExists => f.write_str("function already exists"),
+            Error::LimitConflict => f.write_str("query already contains a LIMIT clause"),
+            Error::Timeout => f.write_str("query timed out"),
+            Error::ResultTooLarge {
+                max_rows,
+                max_bytes,
+            } => write!(
+                f,
+                "query result exceeds the limit of {max_rows} rows or {max_bytes} bytes"
+            ),
+            #[cfg(feature = "sqlcipher")]
+            Error::NotEncrypted => f.write_str("database was opened without a key"),
+            Error::Panic { message } => write!(f, "closure panicked: {message}"),
+            Error::WorkerClosed => f.write_str("connection worker closed"),
+            Error::CommandsLost { count } => {
+                write!(
+                    f,
+                    "connection worker stopped with {count} unprocessed commands"
+                )
+            }
+            Error::Poisoned => f.write_str("connection worker did not respond in time"),
+            Error::Join(err) => write!(f, "connection worker failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Sqlite(err) => Some(err),
+            Error::Io(err) => Some(err),
+            Error::Join(err) => Some(err),
+            Error::ArgumentSet { source, .. } => Some(source.as_ref()),
+            Error::Field { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        Error::Sqlite(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
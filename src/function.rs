@@ -0,0 +1,135 @@
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+
+use rusqlite::functions::FunctionFlags;
+use rusqlite::{params_from_iter, OptionalExtension};
+
+use super::error::Error;
+use super::query::check_arguments;
+use super::sqlite::{Connection, Row, Value};
+
+impl Connection {
+    /// Registers the scalar SQL function, replacing the function with the same
+    /// name and number of arguments.
+    ///
+    /// The function runs on the connection worker. A negative `n_args`
+    /// accepts any number of arguments.
+    pub async fn create_scalar_function<F>(
+        &mut self,
+        name: &str,
+        n_args: i32,
+        f: F,
+    ) -> Result<(), Error>
+    where
+        F: Fn(&[Value]) -> Result<Value, Error> + Send + 'static,
+    {
+        let name = name.to_owned();
+        self.call_worker(move |conn| create_scalar_function(conn, &name, n_args, f))
+            .await
+    }
+
+    /// Executes a statement with the scalar function registered only for
+    /// its duration and collects all resulting query rows.
+    ///
+    /// The function takes `n_args` arguments and is removed once the rows
+    /// are read, even if the query fails. Returns
+    /// [`Error::FunctionAlreadyExists`] if a function with the same name and
+    /// number of arguments, including a built-in one, is already registered.
+    pub async fn query_with_function<S, A, F>(
+        &mut self,
+        statement: S,
+        arguments: A,
+        name: &str,
+        n_args: i32,
+        f: F,
+    ) -> Result<Vec<Row>, Error>
+    where
+        S: Into<String>,
+        A: Into<Vec<Value>>,
+        F: Fn(&[Value]) -> Result<Value, Error> + Send + 'static,
+    {
+        let statement = statement.into();
+        let arguments = arguments.into();
+        let name = name.to_owned();
+        self.call_worker(move |conn| {
+            let exists = conn
+                .query_row(
+                    "SELECT 1 FROM pragma_function_list \
+                        WHERE name = ?1 COLLATE NOCASE AND narg = ?2",
+                    (&name, n_args),
+                    |_| Ok(()),
+                )
+                .optional()?;
+            if exists.is_some() {
+                return Err(Error::FunctionAlreadyExists);
+            }
+            create_scalar_function(conn, &name, n_args, f)?;
+            let rows = (|| -> Result<Vec<Row>, Error> {
+                check_arguments(conn, &arguments)?;
+                // Not cached, since the statement refers to the function.
+                let mut stmt = conn.prepare(&statement)?;
+                let columns: Arc<[String]> =
+                    stmt.column_names().into_iter().map(str::to_owned).collect();
+                let mut rows = stmt.query(params_from_iter(arguments))?;
+                let mut values = Vec::new();
+                while let Some(row) = rows.next()? {
+                    values.push(Row {
+                        columns: columns.clone(),
+                        values: (0..columns.len())
+                            .map(|i| row.get(i))
+                            .collect::<Result<_, _>>()?,
+                    });
+                }
+                Ok(values)
+            })();
+            conn.remove_function(&name, n_args)?;
+            rows
+        })
+        .await
+    }
+
+    /// Registers the collating sequence, replacing the one with the same name.
+    ///
+    /// The comparison runs on the connection worker and can be used as
+    /// `ORDER BY column COLLATE name`.
+    pub async fn create_collation<F>(&mut self, name: &str, cmp: F) -> Result<(), Error>
+    where
+        F: Fn(&str, &str) -> std::cmp::Ordering + Send + 'static,
+    {
+        let name = name.to_owned();
+        let cmp = AssertUnwindSafe(cmp);
+        self.call_worker(move |conn| {
+            conn.create_collation(name.as_str(), move |a, b| (*cmp)(a, b))?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Removes the collating sequence registered with
+    /// [`Connection::create_collation`].
+    pub async fn remove_collation(&mut self, name: &str) -> Result<(), Error> {
+        let name = name.to_owned();
+        self.call_worker(move |conn| Ok(conn.remove_collation(&name)?))
+            .await
+    }
+}
+
+/// Registers the scalar function receiving the arguments as values.
+fn create_scalar_function<F>(
+    conn: &rusqlite::Connection,
+    name: &str,
+    n_args: i32,
+    f: F,
+) -> Result<(), Error>
+where
+    F: Fn(&[Value]) -> Result<Value, Error> + Send + 'static,
+{
+    let f = AssertUnwindSafe(f);
+    conn.create_scalar_function(name, n_args, FunctionFlags::SQLITE_UTF8, move |ctx| {
+        let arguments = (0..ctx.len())
+            .map(|i| ctx.get::<Value>(i))
+            .collect::<Result<Vec<_>, _>>()?;
+        (*f)(&arguments).map_err(|err| rusqlite::Error::UserFunctionError(Box::new(err)))
+    })?;
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use std::ffi::{c_char, c_int, c_void, CStr};
+
+use rusqlite::ffi;
+use tokio::sync::mpsc;
+
+/// A sender of `(database name, frame count)` pairs reported after commits.
+pub(super) type WalSender = mpsc::UnboundedSender<(String, u32)>;
+
+/// The WAL hook installed on the connection, removed on drop.
+///
+/// Must be dropped before the connection it is installed on.
+pub(super) struct WalHook {
+    db: *mut ffi::sqlite3,
+    tx: *mut WalSender,
+}
+
+impl WalHook {
+    pub fn install(conn: &rusqlite::Connection, tx: WalSender) -> Self {
+        let db = unsafe { conn.handle() };
+        let tx = Box::into_raw(Box::new(tx));
+        unsafe { ffi::sqlite3_wal_hook(db, Some(wal_hook), tx.cast()) };
+        Self { db, tx }
+    }
+}
+
+impl Drop for WalHook {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::sqlite3_wal_hook(self.db, None, std::ptr::null_mut());
+            drop(Box::from_raw(self.tx));
+        }
+    }
+}
+
+unsafe extern "C" fn wal_hook(
+    arg: *mut c_void,
+    _db: *mut ffi::sqlite3,
+    name: *const c_char,
+    frames: c_int,
+) -> c_int {
+    let tx = &*(arg as *const WalSender);
+    let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+    // The receiver may be dropped, the hook stays until it is replaced.
+    let _ = tx.send((name, frames as u32));
+    ffi::SQLITE_OK
+}
@@ -1,6 +1,37 @@
+mod blob;
+#[cfg(feature = "sqlcipher")]
+mod cipher;
 mod connection;
+mod csv;
+mod dump;
+mod error;
+mod function;
+#[cfg(feature = "hooks")]
+mod hooks;
+mod limit;
+#[cfg(feature = "bb8")]
+mod manager;
+mod options;
+mod order;
+mod pool;
 mod query;
+mod retry;
 mod sqlite;
 mod transaction;
+mod value;
+mod watchdog;
 
+#[cfg(feature = "sqlcipher")]
+pub use cipher::*;
+pub use csv::*;
+pub use error::*;
+#[cfg(feature = "bb8")]
+pub use manager::*;
+pub use options::*;
+pub use order::*;
+pub use pool::*;
 pub use sqlite::*;
+pub use value::*;
+
+#[cfg(feature = "derive")]
+pub use tokio_sqlite_derive::{FromRow, FromValue, ToValue};
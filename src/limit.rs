@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use super::error::Error;
+use super::sqlite::{Connection, Row, Rows, Value};
+use super::value::value_size;
+
+impl Connection {
+    /// Executes a query and collects a page of at most `limit` resulting rows
+    /// after skipping `offset` rows.
+    ///
+    /// The query is wrapped as a subquery, so its own `ORDER BY` decides the
+    /// page contents. Returns [`Error::LimitConflict`] if the query already
+    /// contains a `LIMIT` clause.
+    pub async fn query_page<S, A>(
+        &mut self,
+        statement: S,
+        arguments: A,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<Row>, Error>
+    where
+        S: AsRef<str>,
+        A: Into<Vec<Value>>,
+    {
+        let statement = statement.as_ref().trim_end().trim_end_matches(';');
+        if has_limit_clause(statement) {
+            return Err(Error::LimitConflict);
+        }
+        let mut arguments = arguments.into();
+        arguments.push(Value::Integer(limit.try_into().unwrap_or(i64::MAX)));
+        arguments.push(Value::Integer(offset.try_into().unwrap_or(i64::MAX)));
+        self.query_all(
+            format!("SELECT * FROM ({statement}) LIMIT ? OFFSET ?"),
+            arguments,
+        )
+        .await
+    }
+
+    /// Executes a statement and collects all resulting query rows, failing
+    /// with [`Error::ResultTooLarge`] once there are more than `max_rows`
+    /// rows or their values take more than `max_bytes` bytes.
+    ///
+    /// Numeric values are counted as 8 bytes. On failure the worker stops
+    /// reading the remaining rows.
+    pub async fn query_bounded<S, A>(
+        &mut self,
+        statement: S,
+        arguments: A,
+        max_rows: usize,
+        max_bytes: usize,
+    ) -> Result<Vec<Row>, Error>
+    where
+        S: Into<String>,
+        A: Into<Vec<Value>>,
+    {
+        let mut rows = self.query(statement, arguments).await?;
+        let mut values = Vec::new();
+        let mut bytes = 0;
+        while let Some(row) = rows.next().await {
+            let row = row?;
+            bytes += row.values.iter().map(value_size).sum::<usize>();
+            if values.len() >= max_rows || bytes > max_bytes {
+                return Err(Error::ResultTooLarge {
+                    max_rows,
+                    max_bytes,
+                });
+            }
+            values.push(row);
+        }
+        Ok(values)
+    }
+
+    /// Executes a statement like [`Connection::query`], failing with
+    /// [`Error::Timeout`] if the rows are not produced within the timeout.
+    ///
+    /// The timeout covers preparing the statement and computing its first
+    /// row, which for aggregates and sorted results is most of the work. On
+    /// timeout the statement is interrupted, so the worker becomes available
+    /// for the next command. The remaining rows are read without the timeout.
+    pub async fn query_timeout<S, A>(
+        &mut self,
+        statement: S,
+        arguments: A,
+        timeout: Duration,
+    ) -> Result<Rows, Error>
+    where
+        S: Into<String>,
+        A: Into<Vec<Value>>,
+    {
+        // Interrupting has no effect until the worker starts the statement,
+        // so it is repeated until the query ends.
+        const INTERRUPT_INTERVAL: Duration = Duration::from_millis(10);
+        let interrupt = self.interrupt_handle();
+        let mut query = std::pin::pin!(self.query(statement, arguments));
+        if let Ok(result) = tokio::time::timeout(timeout, &mut query).await {
+            return result;
+        }
+        loop {
+            interrupt.interrupt();
+            if tokio::time::timeout(INTERRUPT_INTERVAL, &mut query)
+                .await
+                .is_ok()
+            {
+                return Err(Error::Timeout);
+            }
+        }
+    }
+}
+
+/// Returns true if the statement contains the `LIMIT` keyword outside of
+/// literals and quoted identifiers.
+fn has_limit_clause(statement: &str) -> bool {
+    let mut chars = statement.chars().peekable();
+    let mut word = String::new();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            word.push(c);
+            continue;
+        }
+        if word.eq_ignore_ascii_case("limit") {
+            return true;
+        }
+        word.clear();
+        let close = match c {
+            '\'' | '"' | '`' => c,
+            '[' => ']',
+            _ => continue,
+        };
+        for c in chars.by_ref() {
+            if c == close {
+                break;
+            }
+        }
+    }
+    word.eq_ignore_ascii_case("limit")
+}
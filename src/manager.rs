@@ -0,0 +1,49 @@
+use std::path::{Path, PathBuf};
+
+use super::{Connection, ConnectionOptions, Error, OpenFlags};
+
+/// A manager of connections pooled by [`bb8`].
+#[derive(Clone, Debug)]
+pub struct SqliteConnectionManager {
+    path: PathBuf,
+    options: ConnectionOptions,
+}
+
+impl SqliteConnectionManager {
+    /// Creates a manager of connections to the database.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self::with_options(path, ConnectionOptions::new())
+    }
+
+    /// Creates a manager of connections opened using the flags.
+    pub fn with_flags<P: AsRef<Path>>(path: P, flags: OpenFlags) -> Self {
+        Self::with_options(path, ConnectionOptions::new().flags(flags))
+    }
+
+    /// Creates a manager of connections opened using the options.
+    ///
+    /// The pragmas of the options are applied to every new connection.
+    pub fn with_options<P: AsRef<Path>>(path: P, options: ConnectionOptions) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+            options,
+        }
+    }
+}
+
+impl bb8::ManageConnection for SqliteConnectionManager {
+    type Connection = Connection;
+    type Error = Error;
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        Connection::open_with_options(&self.path, self.options.clone()).await
+    }
+
+    async fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        conn.query_row("SELECT 1", []).await.map(|_| ())
+    }
+
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        conn.is_closed()
+    }
+}
@@ -0,0 +1,375 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rusqlite::functions::FunctionFlags;
+use rusqlite::limits::Limit;
+use rusqlite::OpenFlags;
+
+#[cfg(feature = "sqlcipher")]
+use super::SecretKey;
+use super::{Connection, Error};
+
+/// A journal mode of the database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    Wal,
+    Off,
+}
+
+impl JournalMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JournalMode::Delete => "DELETE",
+            JournalMode::Truncate => "TRUNCATE",
+            JournalMode::Persist => "PERSIST",
+            JournalMode::Memory => "MEMORY",
+            JournalMode::Wal => "WAL",
+            JournalMode::Off => "OFF",
+        }
+    }
+}
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// A function that runs the connection worker on a custom executor.
+///
+/// The worker blocks the thread it runs on until the connection is closed.
+#[derive(Clone)]
+pub(super) struct Spawner(pub Arc<dyn Fn(Task) + Send + Sync>);
+
+impl fmt::Debug for Spawner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Spawner")
+    }
+}
+
+/// A function deciding whether a failed operation is retried.
+#[derive(Clone)]
+pub(super) struct RetryPredicate(pub Arc<dyn Fn(&Error) -> bool + Send + Sync>);
+
+impl Default for RetryPredicate {
+    fn default() -> Self {
+        Self(Arc::new(Error::is_busy))
+    }
+}
+
+impl fmt::Debug for RetryPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RetryPredicate")
+    }
+}
+
+/// A policy of retrying statements that fail because the database is busy
+/// or locked by another connection.
+///
+/// See [`Connection::execute_with_retry`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub(super) attempts: usize,
+    pub(super) delay: Duration,
+    pub(super) backoff: u32,
+}
+
+impl RetryPolicy {
+    /// Creates a policy running the statement at most `attempts` times,
+    /// waiting for the delay between attempts.
+    pub fn new(attempts: usize, delay: Duration) -> Self {
+        Self {
+            attempts,
+            delay,
+            backoff: 1,
+        }
+    }
+
+    /// Multiplies the delay by the factor after every failed attempt.
+    pub fn backoff(mut self, factor: u32) -> Self {
+        self.backoff = factor;
+        self
+    }
+}
+
+/// Options used to open a connection.
+#[derive(Clone, Debug)]
+pub struct ConnectionOptions {
+    pub(super) flags: OpenFlags,
+    pub(super) journal_mode: Option<JournalMode>,
+    pub(super) foreign_keys: Option<bool>,
+    pub(super) busy_timeout: Option<Duration>,
+    pub(super) watchdog_timeout: Option<Duration>,
+    pub(super) spawner: Option<Spawner>,
+    pub(super) checkpoint_on_idle: Option<Duration>,
+    pub(super) retry_predicate: RetryPredicate,
+    pub(super) random_seed: Option<u64>,
+    #[cfg(feature = "sqlcipher")]
+    pub(super) key: Option<SecretKey>,
+}
+
+impl ConnectionOptions {
+    pub fn new() -> Self {
+        Self {
+            flags: OpenFlags::default(),
+            journal_mode: None,
+            foreign_keys: None,
+            busy_timeout: None,
+            watchdog_timeout: None,
+            spawner: None,
+            checkpoint_on_idle: None,
+            retry_predicate: RetryPredicate::default(),
+            random_seed: None,
+            #[cfg(feature = "sqlcipher")]
+            key: None,
+        }
+    }
+
+    /// Sets the flags used to open the database.
+    pub fn flags(mut self, flags: OpenFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Enables or disables interpretation of the path as a URI filename, such
+    /// as `file:memdb?mode=memory&cache=shared`.
+    ///
+    /// Enabled by the default flags. Connections to the same shared-cache
+    /// in-memory URI share the database while any of them is open.
+    pub fn uri(mut self, enabled: bool) -> Self {
+        self.flags.set(OpenFlags::SQLITE_OPEN_URI, enabled);
+        self
+    }
+
+    /// Sets the journal mode applied right after the database is opened.
+    pub fn journal_mode(mut self, mode: JournalMode) -> Self {
+        self.journal_mode = Some(mode);
+        self
+    }
+
+    /// Enables or disables enforcement of foreign key constraints.
+    pub fn foreign_keys(mut self, enabled: bool) -> Self {
+        self.foreign_keys = Some(enabled);
+        self
+    }
+
+    /// Sets the busy timeout applied right after the database is opened.
+    ///
+    /// See [`Connection::set_busy_timeout`].
+    pub fn busy_timeout(mut self, timeout: Duration) -> Self {
+        self.busy_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the time after which a command that has not completed poisons
+    /// the connection.
+    ///
+    /// The stuck worker cannot be stopped, but all following calls fail with
+    /// [`Error::Poisoned`] instead of waiting for it.
+    pub fn watchdog_timeout(mut self, timeout: Duration) -> Self {
+        self.watchdog_timeout = Some(timeout);
+        self
+    }
+
+    /// Enables a passive WAL checkpoint once the connection has been idle for
+    /// the duration.
+    ///
+    /// Keeps the WAL small without blocking active work. The checkpoint runs
+    /// on a task of the tokio runtime the connection was opened in.
+    pub fn checkpoint_on_idle(mut self, idle: Duration) -> Self {
+        self.checkpoint_on_idle = Some(idle);
+        self
+    }
+
+    /// Sets the function used to launch the connection worker.
+    ///
+    /// By default the worker runs on the blocking pool of tokio. The spawner
+    /// must run the worker on a thread where blocking is allowed.
+    pub fn spawner<F>(mut self, spawner: F) -> Self
+    where
+        F: Fn(Box<dyn FnOnce() + Send>) + Send + Sync + 'static,
+    {
+        self.spawner = Some(Spawner(Arc::new(spawner)));
+        self
+    }
+
+    /// Sets the function deciding which errors are retried by
+    /// [`Connection::execute_with_retry`] and [`Connection::transaction_retry`].
+    ///
+    /// By default only busy and locked errors are retried, see
+    /// [`Error::is_busy`].
+    pub fn retry_predicate<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&Error) -> bool + Send + Sync + 'static,
+    {
+        self.retry_predicate = RetryPredicate(Arc::new(predicate));
+        self
+    }
+
+    /// Replaces the `random()` and `randomblob()` functions with ones that
+    /// produce the same sequence for the same seed.
+    ///
+    /// Intended for reproducible tests, the generated values are not suitable
+    /// for cryptographic use.
+    pub fn random_seed(mut self, seed: u64) -> Self {
+        self.random_seed = Some(seed);
+        self
+    }
+
+    /// Sets the key of the database encrypted by SQLCipher.
+    ///
+    /// The key is applied before any other pragma, and opening fails if it
+    /// does not decrypt the database.
+    #[cfg(feature = "sqlcipher")]
+    pub fn key(mut self, key: SecretKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Applies the pragmas to the newly opened connection.
+    ///
+    /// Fails if SQLite keeps another journal mode than the requested one.
+    pub(super) fn apply(&self, conn: &rusqlite::Connection) -> Result<(), Error> {
+        #[cfg(feature = "sqlcipher")]
+        if let Some(key) = &self.key {
+            key.apply(conn, "key")?;
+            // The key is checked on the first access to the database.
+            conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |_| Ok(()))?;
+        }
+        if let Some(mode) = self.journal_mode {
+            let applied: String =
+                conn.pragma_update_and_check(None, "journal_mode", mode.as_str(), |row| {
+                    row.get(0)
+                })?;
+            if !applied.eq_ignore_ascii_case(mode.as_str()) {
+                return Err(Error::JournalMode {
+                    requested: mode,
+                    applied,
+                });
+            }
+        }
+        if let Some(enabled) = self.foreign_keys {
+            conn.pragma_update(None, "foreign_keys", enabled)?;
+        }
+        if let Some(timeout) = self.busy_timeout {
+            conn.busy_timeout(timeout)?;
+        }
+        if let Some(seed) = self.random_seed {
+            create_random_functions(conn, seed)?;
+        }
+        Ok(())
+    }
+}
+
+/// Registers `random()` and `randomblob()` backed by a SplitMix64 generator.
+fn create_random_functions(conn: &rusqlite::Connection, seed: u64) -> Result<(), Error> {
+    const GAMMA: u64 = 0x9e3779b97f4a7c15;
+    let state = Arc::new(AtomicU64::new(seed));
+    let next = move || {
+        let mut z = state
+            .fetch_add(GAMMA, Ordering::Relaxed)
+            .wrapping_add(GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    };
+    let random = next.clone();
+    conn.create_scalar_function("random", 0, FunctionFlags::SQLITE_UTF8, move |_| {
+        Ok(random() as i64)
+    })?;
+    let limit = conn.limit(Limit::SQLITE_LIMIT_LENGTH) as usize;
+    conn.create_scalar_function("randomblob", 1, FunctionFlags::SQLITE_UTF8, move |ctx| {
+        // Like the builtin, lengths below one produce a single byte.
+        let len = ctx.get::<i64>(0).unwrap_or(1).max(1) as usize;
+        if len > limit {
+            let err = Error::ValueTooLarge { size: len, limit };
+            return Err(rusqlite::Error::UserFunctionError(Box::new(err)));
+        }
+        let mut blob = Vec::with_capacity(len + 8);
+        while blob.len() < len {
+            blob.extend_from_slice(&next().to_le_bytes());
+        }
+        blob.truncate(len);
+        Ok(blob)
+    })?;
+    Ok(())
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A builder of a connection to a SQLite database.
+///
+/// All pragmas are applied by the worker before the connection is returned.
+#[derive(Clone, Debug)]
+pub struct ConnectionBuilder {
+    path: PathBuf,
+    options: ConnectionOptions,
+}
+
+impl ConnectionBuilder {
+    /// Creates a builder of a connection to an in-memory database.
+    pub fn new() -> Self {
+        Self {
+            path: PathBuf::from(":memory:"),
+            options: ConnectionOptions::new(),
+        }
+    }
+
+    /// Sets the path to the database.
+    pub fn path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.path = path.as_ref().to_owned();
+        self
+    }
+
+    /// Sets the flags used to open the database.
+    pub fn flags(mut self, flags: OpenFlags) -> Self {
+        self.options = self.options.flags(flags);
+        self
+    }
+
+    /// Enables or disables interpretation of the path as a URI filename.
+    ///
+    /// See [`ConnectionOptions::uri`].
+    pub fn uri(mut self, enabled: bool) -> Self {
+        self.options = self.options.uri(enabled);
+        self
+    }
+
+    /// Sets the journal mode of the database.
+    pub fn journal_mode(mut self, mode: JournalMode) -> Self {
+        self.options = self.options.journal_mode(mode);
+        self
+    }
+
+    /// Enables or disables enforcement of foreign key constraints.
+    pub fn foreign_keys(mut self, enabled: bool) -> Self {
+        self.options = self.options.foreign_keys(enabled);
+        self
+    }
+
+    /// Sets the time to wait for locks held by other connections.
+    pub fn busy_timeout(mut self, timeout: Duration) -> Self {
+        self.options = self.options.busy_timeout(timeout);
+        self
+    }
+
+    /// Opens the connection.
+    ///
+    /// Returns the error of the first pragma that fails to apply.
+    pub async fn open(self) -> Result<Connection, Error> {
+        Connection::open_with_options(self.path, self.options).await
+    }
+}
+
+impl Default for ConnectionBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
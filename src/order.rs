@@ -0,0 +1,149 @@
+use std::borrow::Cow;
+
+/// The first SQLite version that supports `NULLS FIRST` and `NULLS LAST`.
+const NULLS_ORDER_VERSION: i32 = 3_030_000;
+
+/// Returns the statement with `NULLS FIRST` and `NULLS LAST` rewritten by
+/// [`rewrite_nulls_order`] if the linked SQLite library does not support them.
+pub fn nulls_order_compat(statement: &str) -> Cow<'_, str> {
+    if rusqlite::version_number() >= NULLS_ORDER_VERSION {
+        Cow::Borrowed(statement)
+    } else {
+        Cow::Owned(rewrite_nulls_order(statement))
+    }
+}
+
+/// Rewrites `NULLS FIRST` and `NULLS LAST` terms of the top-level `ORDER BY`
+/// clause into equivalent `CASE` expressions.
+///
+/// For example `ORDER BY b DESC NULLS LAST` becomes
+/// `ORDER BY CASE WHEN b IS NULL THEN 1 ELSE 0 END, b DESC`.
+pub fn rewrite_nulls_order(statement: &str) -> String {
+    let upper = statement.to_ascii_uppercase();
+    let indices = top_level_indices(statement);
+    let Some(start) = indices
+        .iter()
+        .rev()
+        .find_map(|&i| match_keywords(&upper, i, &["ORDER", "BY"]))
+    else {
+        return statement.to_owned();
+    };
+    let end = indices
+        .iter()
+        .filter(|&&i| i >= start)
+        .find(|&&i| upper[i..].starts_with(';') || match_keywords(&upper, i, &["LIMIT"]).is_some())
+        .copied()
+        .unwrap_or(statement.len());
+    let mut terms = Vec::new();
+    let mut term_start = start;
+    for &i in indices.iter().filter(|&&i| i >= start && i < end) {
+        if statement[i..].starts_with(',') {
+            terms.push(&statement[term_start..i]);
+            term_start = i + 1;
+        }
+    }
+    terms.push(&statement[term_start..end]);
+    let terms: Vec<_> = terms.into_iter().map(rewrite_term).collect();
+    let mut result = statement[..start].to_owned();
+    result.push(' ');
+    result.push_str(&terms.join(", "));
+    if end < statement.len() {
+        result.push(' ');
+        result.push_str(&statement[end..]);
+    }
+    result
+}
+
+fn rewrite_term(term: &str) -> String {
+    let term = term.trim();
+    let upper = term.to_ascii_uppercase();
+    let (rest, nulls_last) = if let Some(rest) = strip_keyword_suffix(&upper, "LAST") {
+        (rest, true)
+    } else if let Some(rest) = strip_keyword_suffix(&upper, "FIRST") {
+        (rest, false)
+    } else {
+        return term.to_owned();
+    };
+    let Some(rest) = strip_keyword_suffix(&upper[..rest], "NULLS") else {
+        return term.to_owned();
+    };
+    let (expr, order) = match strip_keyword_suffix(&upper[..rest], "DESC")
+        .or_else(|| strip_keyword_suffix(&upper[..rest], "ASC"))
+    {
+        Some(expr) => (term[..expr].trim_end(), term[expr..rest].trim()),
+        None => (term[..rest].trim_end(), ""),
+    };
+    let (when_null, otherwise) = if nulls_last { (1, 0) } else { (0, 1) };
+    let mut result =
+        format!("CASE WHEN {expr} IS NULL THEN {when_null} ELSE {otherwise} END, {expr}");
+    if !order.is_empty() {
+        result.push(' ');
+        result.push_str(order);
+    }
+    result
+}
+
+/// Returns the length of the string without the trailing keyword.
+fn strip_keyword_suffix(upper: &str, keyword: &str) -> Option<usize> {
+    let rest = upper.trim_end().strip_suffix(keyword)?;
+    if rest.ends_with(|c: char| c.is_whitespace()) {
+        Some(rest.len())
+    } else {
+        None
+    }
+}
+
+/// Returns the end of the keywords if they start at the index.
+fn match_keywords(upper: &str, mut index: usize, keywords: &[&str]) -> Option<usize> {
+    if upper[..index].ends_with(is_word_char) {
+        return None;
+    }
+    for (i, keyword) in keywords.iter().enumerate() {
+        if i > 0 {
+            let rest = &upper[index..];
+            let trimmed = rest.trim_start();
+            if trimmed.len() == rest.len() {
+                return None;
+            }
+            index += rest.len() - trimmed.len();
+        }
+        if !upper[index..].starts_with(keyword) {
+            return None;
+        }
+        index += keyword.len();
+    }
+    if upper[index..].starts_with(is_word_char) {
+        return None;
+    }
+    Some(index)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Returns indices of characters outside of quotes and parentheses.
+fn top_level_indices(statement: &str) -> Vec<usize> {
+    let mut indices = Vec::new();
+    let mut depth = 0usize;
+    let mut quote = None;
+    for (i, c) in statement.char_indices() {
+        match quote {
+            Some(end) => {
+                if c == end {
+                    quote = None;
+                }
+                continue;
+            }
+            None => match c {
+                '\'' | '"' | '`' => quote = Some(c),
+                '[' => quote = Some(']'),
+                '(' => depth += 1,
+                ')' => depth = depth.saturating_sub(1),
+                _ if depth == 0 => indices.push(i),
+                _ => {}
+            },
+        }
+    }
+    indices
+}
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::{Connection, ConnectionOptions, Error, Status, Value};
+
+/// A pool of connections to the same database.
+///
+/// Each connection has its own worker, so queries on different connections
+/// run concurrently. Connections are opened lazily and reused once released.
+/// Use the WAL journal mode to let readers proceed while a writer is active.
+///
+/// Every connection to `:memory:` opens a separate database, so pools should
+/// use file paths.
+#[derive(Clone)]
+pub struct Pool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    path: PathBuf,
+    options: ConnectionOptions,
+    max_size: usize,
+    semaphore: Arc<Semaphore>,
+    idle: Mutex<Vec<Connection>>,
+    /// SQL of the named statements shared by all connections.
+    statements: Mutex<HashMap<String, String>>,
+    /// Rows changed by the connections until they were last released.
+    total_changes: AtomicU64,
+}
+
+impl Pool {
+    /// Creates a pool of at most `max_size` connections to the database.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_size` is zero.
+    pub fn new<P: AsRef<Path>>(path: P, options: ConnectionOptions, max_size: usize) -> Self {
+        assert!(max_size > 0, "pool size must be positive");
+        Self {
+            inner: Arc::new(PoolInner {
+                path: path.as_ref().to_owned(),
+                options,
+                max_size,
+                semaphore: Arc::new(Semaphore::new(max_size)),
+                idle: Mutex::new(Vec::with_capacity(max_size)),
+                statements: Mutex::new(HashMap::new()),
+                total_changes: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Returns the maximum number of connections.
+    pub fn max_size(&self) -> usize {
+        self.inner.max_size
+    }
+
+    /// Returns the number of opened connections that are not in use.
+    pub fn idle(&self) -> usize {
+        self.inner.idle.lock().unwrap().len()
+    }
+
+    /// Returns the total number of rows inserted, updated or deleted through
+    /// the connections of the pool.
+    ///
+    /// The changes of a connection are counted once it is released, so the
+    /// changes of connections currently in use are not included yet.
+    pub fn total_changes(&self) -> u64 {
+        self.inner.total_changes.load(Ordering::Relaxed)
+    }
+
+    /// Registers the statement under the name, replacing the statement
+    /// registered before with the same name.
+    ///
+    /// The statement is prepared on each connection the first time it is
+    /// used there and taken from the statement cache of that connection
+    /// afterwards.
+    pub fn register_statement<N, S>(&self, name: N, statement: S)
+    where
+        N: Into<String>,
+        S: Into<String>,
+    {
+        let mut statements = self.inner.statements.lock().unwrap();
+        statements.insert(name.into(), statement.into());
+    }
+
+    /// Takes a connection from the pool, waiting until one is released if
+    /// all of them are in use.
+    ///
+    /// Closed connections are discarded and replaced by new ones.
+    pub async fn acquire(&self) -> Result<PooledConnection, Error> {
+        let permit = self
+            .inner
+            .semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("pool semaphore is never closed");
+        loop {
+            let conn = self.inner.idle.lock().unwrap().pop();
+            match conn {
+                Some(conn) if conn.is_closed() => continue,
+                Some(conn) => return Ok(self.pooled(conn, permit)),
+                None => break,
+            }
+        }
+        let conn =
+            Connection::open_with_options(&self.inner.path, self.inner.options.clone()).await?;
+        Ok(self.pooled(conn, permit))
+    }
+
+    fn pooled(&self, conn: Connection, permit: OwnedSemaphorePermit) -> PooledConnection {
+        PooledConnection {
+            changes: conn.changes(),
+            conn: Some(conn),
+            pool: self.inner.clone(),
+            _permit: permit,
+        }
+    }
+}
+
+/// A connection taken from the [`Pool`].
+///
+/// The connection is returned to the pool on drop.
+pub struct PooledConnection {
+    conn: Option<Connection>,
+    pool: Arc<PoolInner>,
+    /// Rows changed by the connection before it was acquired.
+    changes: u64,
+    // Released after the connection is returned to the pool.
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledConnection {
+    /// Executes the statement registered on the pool under the name.
+    ///
+    /// Returns [`Error::UnknownStatement`] if no statement is registered
+    /// under the name.
+    pub async fn execute_statement<A>(&mut self, name: &str, arguments: A) -> Result<Status, Error>
+    where
+        A: Into<Vec<Value>>,
+    {
+        let statement = self.statement(name)?;
+        let conn = self.conn.as_mut().unwrap();
+        conn.execute_cached(statement, arguments.into()).await
+    }
+
+    /// Executes the query registered on the pool under the name and
+    /// transforms the resulting rows on the worker.
+    ///
+    /// Returns [`Error::UnknownStatement`] if no statement is registered
+    /// under the name.
+    pub async fn query_statement<T, F, A>(
+        &mut self,
+        name: &str,
+        arguments: A,
+        f: F,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: Send + 'static,
+        F: FnMut(&rusqlite::Row) -> Result<T, Error> + Send + 'static,
+        A: Into<Vec<Value>>,
+    {
+        let statement = self.statement(name)?;
+        let conn = self.conn.as_mut().unwrap();
+        conn.query_cached(statement, arguments.into(), f).await
+    }
+
+    fn statement(&self, name: &str) -> Result<String, Error> {
+        let statements = self.pool.statements.lock().unwrap();
+        statements
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::UnknownStatement {
+                name: name.to_owned(),
+            })
+    }
+}
+
+impl Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.conn.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            let changes = conn.changes().saturating_sub(self.changes);
+            self.pool
+                .total_changes
+                .fetch_add(changes, Ordering::Relaxed);
+            if !conn.is_closed() {
+                self.pool.idle.lock().unwrap().push(conn);
+            }
+        }
+    }
+}
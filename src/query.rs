@@ -1,23 +1,215 @@
-use rusqlite::{params_from_iter, Error};
+use std::sync::Arc;
+use std::time::Instant;
+
+use rusqlite::limits::Limit;
+use rusqlite::TransactionBehavior;
 use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::connection::ChangeCounter;
+use super::watchdog::Watchdog;
+use super::{Error, QueryMetrics, Row, Status, Value};
 
-use super::{Row, Status, Value};
+/// Arguments bound to statement parameters.
+pub(super) enum Arguments {
+    Positional(Vec<Value>),
+    Named(Vec<(String, Value)>),
+}
+
+impl Arguments {
+    pub fn values(&self) -> Box<dyn Iterator<Item = &Value> + '_> {
+        match self {
+            Arguments::Positional(values) => Box::new(values.iter()),
+            Arguments::Named(values) => Box::new(values.iter().map(|(_, v)| v)),
+        }
+    }
+
+    /// Binds the arguments to the statement parameters.
+    pub fn bind(&self, stmt: &mut rusqlite::Statement) -> Result<(), Error> {
+        match self {
+            Arguments::Positional(values) => {
+                let count = stmt.parameter_count();
+                if values.len() != count {
+                    return Err(rusqlite::Error::InvalidParameterCount(values.len(), count).into());
+                }
+                for (i, value) in values.iter().enumerate() {
+                    stmt.raw_bind_parameter(i + 1, value)?;
+                }
+            }
+            Arguments::Named(values) => {
+                for (name, value) in values {
+                    let index = stmt
+                        .parameter_index(name)?
+                        .ok_or_else(|| rusqlite::Error::InvalidParameterName(name.clone()))?;
+                    stmt.raw_bind_parameter(index, value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
 
 pub(super) struct ExecuteCommand {
     pub statement: String,
-    pub arguments: Vec<Value>,
+    pub arguments: Arguments,
+    pub enqueued: Instant,
     pub tx: oneshot::Sender<Result<Status, Error>>,
 }
 
+impl ExecuteCommand {
+    /// Returns the metrics of the command whose processing starts now.
+    pub fn metrics(&self) -> QueryMetrics {
+        QueryMetrics {
+            queue_wait: self.enqueued.elapsed(),
+        }
+    }
+}
+
 pub(super) struct QueryCommand {
     pub statement: String,
-    pub arguments: Vec<Value>,
+    pub arguments: Arguments,
+    /// The number of rows the worker may read ahead of the consumer.
+    pub capacity: usize,
+    pub enqueued: Instant,
     pub tx: oneshot::Sender<Result<QueryHandle, Error>>,
 }
 
+impl QueryCommand {
+    /// Returns the metrics of the command whose processing starts now.
+    pub fn metrics(&self) -> QueryMetrics {
+        QueryMetrics {
+            queue_wait: self.enqueued.elapsed(),
+        }
+    }
+}
+
+/// Checks that text and blob arguments fit into the length limit of the connection.
+pub(super) fn check_arguments<'a>(
+    conn: &rusqlite::Connection,
+    arguments: impl IntoIterator<Item = &'a Value>,
+) -> Result<(), Error> {
+    let limit = conn.limit(Limit::SQLITE_LIMIT_LENGTH) as usize;
+    for argument in arguments {
+        let size = match argument {
+            Value::Text(v) => v.len(),
+            Value::Blob(v) => v.len(),
+            _ => continue,
+        };
+        if size > limit {
+            return Err(Error::ValueTooLarge { size, limit });
+        }
+    }
+    Ok(())
+}
+
+/// Executes a statement that does not return the resulting rows.
+pub(super) fn execute(conn: &rusqlite::Connection, cmd: &ExecuteCommand) -> Result<Status, Error> {
+    let metrics = cmd.metrics();
+    let mut stmt = conn.prepare_cached(&cmd.statement)?;
+    let rows_affected = execute_set(conn, &mut stmt, &cmd.arguments)?;
+    Ok(Status {
+        rows_affected,
+        last_insert_id: Some(conn.last_insert_rowid()),
+        metrics,
+    })
+}
+
+/// Executes a statement that returns the resulting rows, sending them until
+/// the query handle is dropped.
+pub(super) fn blocking_query(
+    conn: &rusqlite::Connection,
+    cmd: QueryCommand,
+    changes: &ChangeCounter,
+    watchdog: Watchdog,
+) {
+    let metrics = cmd.metrics();
+    if let Err(err) = check_arguments(conn, cmd.arguments.values()) {
+        let _ = cmd.tx.send(Err(err));
+        return;
+    }
+    let stmt = match conn.prepare_cached(&cmd.statement) {
+        Ok(stmt) => stmt,
+        Err(err) => {
+            let _ = cmd.tx.send(Err(err.into()));
+            return;
+        }
+    };
+    let task = QueryTask::new(conn, stmt, cmd.arguments, cmd.capacity, metrics, watchdog);
+    task.blocking_run(cmd.tx, || changes.update(conn));
+}
+
+/// Executes the statement once for every argument set.
+///
+/// Returns the total number of affected rows.
+pub(super) fn execute_many(
+    conn: &rusqlite::Connection,
+    statement: &str,
+    arguments: Vec<Vec<Value>>,
+) -> Result<usize, Error> {
+    let mut stmt = conn.prepare_cached(statement)?;
+    let mut rows_affected = 0;
+    for (index, arguments) in arguments.into_iter().enumerate() {
+        rows_affected +=
+            execute_set(conn, &mut stmt, &Arguments::Positional(arguments)).map_err(|err| {
+                Error::ArgumentSet {
+                    index,
+                    source: Box::new(err),
+                }
+            })?;
+    }
+    Ok(rows_affected)
+}
+
+/// Inserts every argument set inside a transaction and returns the rowids
+/// of the first and the last inserted rows.
+pub(super) fn insert_many(
+    conn: &mut rusqlite::Connection,
+    statement: &str,
+    arguments: Vec<Vec<Value>>,
+) -> Result<(i64, i64), Error> {
+    if !conn.is_autocommit() {
+        return Err(Error::TransactionAlreadyOpen);
+    }
+    let transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let mut range = (0, 0);
+    {
+        let mut stmt = transaction.prepare_cached(statement)?;
+        for (index, arguments) in arguments.into_iter().enumerate() {
+            execute_set(&transaction, &mut stmt, &Arguments::Positional(arguments)).map_err(
+                |err| Error::ArgumentSet {
+                    index,
+                    source: Box::new(err),
+                },
+            )?;
+            let rowid = transaction.last_insert_rowid();
+            if index == 0 {
+                range.0 = rowid;
+            }
+            range.1 = rowid;
+        }
+    }
+    transaction.commit()?;
+    Ok(range)
+}
+
+fn execute_set(
+    conn: &rusqlite::Connection,
+    stmt: &mut rusqlite::Statement,
+    arguments: &Arguments,
+) -> Result<usize, Error> {
+    check_arguments(conn, arguments.values())?;
+    arguments.bind(stmt)?;
+    Ok(stmt.raw_execute()?)
+}
+
 pub(super) struct QueryHandle {
-    columns: Vec<String>,
+    columns: Arc<[String]>,
+    decltypes: Vec<Option<String>>,
     rx: mpsc::Receiver<Result<Row, Error>>,
+    status_rx: oneshot::Receiver<Status>,
+    status: Option<Status>,
+    metrics: QueryMetrics,
+    watchdog: Watchdog,
 }
 
 impl QueryHandle {
@@ -25,67 +217,176 @@ impl QueryHandle {
         &self.columns
     }
 
+    pub fn decltypes(&self) -> &[Option<String>] {
+        &self.decltypes
+    }
+
+    pub fn metrics(&self) -> QueryMetrics {
+        self.metrics
+    }
+
+    /// Returns the status sent by the worker once all rows are read.
+    pub fn status(&mut self) -> Option<Status> {
+        if let Ok(status) = self.status_rx.try_recv() {
+            self.status = Some(status);
+        }
+        self.status.clone()
+    }
+
+    /// Takes the remaining rows as a stream, leaving the handle exhausted.
+    ///
+    /// Like the dump stream, rows received from the stream are not watched.
+    pub fn take_stream(&mut self) -> ReceiverStream<Result<Row, Error>> {
+        let (_, rx) = mpsc::channel(1);
+        ReceiverStream::new(std::mem::replace(&mut self.rx, rx))
+    }
+
+    /// Stops the worker from reading further rows.
+    pub fn close(&mut self) {
+        self.rx.close();
+    }
+
     pub async fn next(&mut self) -> Option<Result<Row, Error>> {
-        self.rx.recv().await
+        let rx = &mut self.rx;
+        self.watchdog
+            .watch(async move { Ok(rx.recv().await) })
+            .await
+            .unwrap_or_else(|err| Some(Err(err)))
     }
 }
 
-pub(super) struct QueryTask<'a> {
-    stmt: rusqlite::Statement<'a>,
-    arguments: Vec<Value>,
+/// Sends rows of the statement taken from the statement cache, which gets
+/// the statement back once the task is finished.
+struct QueryTask<'a> {
+    conn: &'a rusqlite::Connection,
+    stmt: rusqlite::CachedStatement<'a>,
+    arguments: Arguments,
+    capacity: usize,
+    metrics: QueryMetrics,
+    watchdog: Watchdog,
 }
 
 impl<'a> QueryTask<'a> {
-    pub fn new(stmt: rusqlite::Statement<'a>, arguments: Vec<Value>) -> Self {
-        Self { stmt, arguments }
+    fn new(
+        conn: &'a rusqlite::Connection,
+        stmt: rusqlite::CachedStatement<'a>,
+        arguments: Arguments,
+        capacity: usize,
+        metrics: QueryMetrics,
+        watchdog: Watchdog,
+    ) -> Self {
+        Self {
+            conn,
+            stmt,
+            arguments,
+            capacity,
+            metrics,
+            watchdog,
+        }
     }
 
-    pub fn blocking_run(mut self, handle_rx: oneshot::Sender<Result<QueryHandle, Error>>) {
-        let columns: Vec<_> = self
+    /// Runs the query, calling `done` once the statement stops returning rows.
+    fn blocking_run<D: Fn()>(
+        mut self,
+        handle_rx: oneshot::Sender<Result<QueryHandle, Error>>,
+        done: D,
+    ) {
+        let columns: Arc<[String]> = self
             .stmt
             .column_names()
             .iter_mut()
             .map(|v| v.to_owned())
             .collect();
-        let columns_len = columns.len();
-        let mut rows = match self
+        let decltypes = self
             .stmt
-            .query(params_from_iter(self.arguments.into_iter()))
-        {
-            Ok(rows) => rows,
-            Err(err) => {
-                let _ = handle_rx.send(Err(err));
-                return;
-            }
+            .columns()
+            .iter()
+            .map(|v| v.decl_type().map(str::to_owned))
+            .collect();
+        if let Err(err) = self.arguments.bind(&mut self.stmt) {
+            let _ = handle_rx.send(Err(err));
+            return;
+        }
+        let readonly = self.stmt.readonly();
+        let mut rows = self.stmt.raw_query();
+        // The handle is sent once the first row is ready, so waiting for it
+        // covers the time the statement takes to start returning rows.
+        let mut next = next_row(&mut rows, &columns);
+        if !matches!(next, Ok(Some(_))) {
+            done();
+        }
+        // The bounded channel blocks the worker once the consumer falls behind.
+        let (tx, rx) = mpsc::channel(self.capacity.max(1));
+        let (status_tx, status_rx) = oneshot::channel();
+        let handle = QueryHandle {
+            columns: columns.clone(),
+            decltypes,
+            rx,
+            status_rx,
+            status: None,
+            metrics: self.metrics,
+            watchdog: self.watchdog,
         };
-        let (tx, rx) = mpsc::channel(1);
-        if let Err(_) = handle_rx.send(Ok(QueryHandle { columns, rx })) {
+        if handle_rx.send(Ok(handle)).is_err() {
             // Drop query if nobody listens result.
             return;
         }
+        // Dropping the rows resets the statement, which then returns to the
+        // statement cache, or is finalized if the cache is disabled.
         loop {
-            let row = match rows.next() {
+            if tx.is_closed() {
+                // Stop stepping once the consumer has dropped the rows.
+                return;
+            }
+            let row = match next {
                 Ok(Some(row)) => row,
-                Ok(None) => return,
+                Ok(None) => {
+                    // Sent before the rows channel is closed, so the status is
+                    // available once the consumer has read all rows. The
+                    // counters of the connection are left from an earlier
+                    // statement unless this one writes, as with `RETURNING`.
+                    let status = if readonly {
+                        Status {
+                            rows_affected: 0,
+                            last_insert_id: None,
+                            metrics: self.metrics,
+                        }
+                    } else {
+                        Status {
+                            rows_affected: self.conn.changes() as usize,
+                            last_insert_id: Some(self.conn.last_insert_rowid()),
+                            metrics: self.metrics,
+                        }
+                    };
+                    let _ = status_tx.send(status);
+                    return;
+                }
                 Err(err) => {
                     _ = tx.blocking_send(Err(err));
                     return;
                 }
             };
-            let mut values = Vec::with_capacity(columns_len);
-            for i in 0..columns_len {
-                let value = match row.get(i) {
-                    Ok(value) => value,
-                    Err(err) => {
-                        _ = tx.blocking_send(Err(err));
-                        return;
-                    }
-                };
-                values.push(value);
-            }
-            if let Err(_) = tx.blocking_send(Ok(Row { values })) {
+            if tx.blocking_send(Ok(row)).is_err() {
                 return;
             }
+            next = next_row(&mut rows, &columns);
+            if !matches!(next, Ok(Some(_))) {
+                done();
+            }
         }
     }
 }
+
+/// Reads the next row of the statement, returning `None` once it is done.
+fn next_row(rows: &mut rusqlite::Rows, columns: &Arc<[String]>) -> Result<Option<Row>, Error> {
+    let Some(row) = rows.next()? else {
+        return Ok(None);
+    };
+    let values = (0..columns.len())
+        .map(|i| row.get(i))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Some(Row {
+        columns: columns.clone(),
+        values,
+    }))
+}
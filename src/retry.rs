@@ -0,0 +1,66 @@
+use super::error::Error;
+use super::options::RetryPolicy;
+use super::sqlite::{BoxFuture, Connection, Status, Transaction, Value};
+
+impl Connection {
+    /// Runs the closure inside a new transaction like
+    /// [`Connection::transaction_scope`], running the whole transaction again
+    /// if it fails with an error accepted by the retry predicate.
+    ///
+    /// The transaction is run at most `attempts` times. See
+    /// [`ConnectionOptions::retry_predicate`](crate::ConnectionOptions::retry_predicate).
+    pub async fn transaction_retry<F, T>(&mut self, attempts: usize, mut f: F) -> Result<T, Error>
+    where
+        F: for<'t, 'c> FnMut(&'t mut Transaction<'c>) -> BoxFuture<'t, Result<T, Error>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match self.transaction_scope(&mut f).await {
+                Err(err) if attempt < attempts && self.is_retryable(&err) => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+
+    /// Returns true if the error is accepted by the retry predicate.
+    fn is_retryable(&self, err: &Error) -> bool {
+        (self.retry_predicate.0)(err)
+    }
+
+    /// Executes the statement like [`Connection::execute`], running it again
+    /// after the policy delay if it fails with an error accepted by the retry
+    /// predicate.
+    ///
+    /// Errors inside an open transaction, where earlier statements may have
+    /// already succeeded, are never retried. See
+    /// [`ConnectionOptions::retry_predicate`](crate::ConnectionOptions::retry_predicate).
+    pub async fn execute_with_retry<S, A>(
+        &mut self,
+        statement: S,
+        arguments: A,
+        policy: RetryPolicy,
+    ) -> Result<Status, Error>
+    where
+        S: Into<String>,
+        A: Into<Vec<Value>>,
+    {
+        let statement = statement.into();
+        let arguments = arguments.into();
+        let mut delay = policy.delay;
+        let mut attempt = 1;
+        loop {
+            match self.execute(statement.clone(), arguments.clone()).await {
+                Err(err)
+                    if attempt < policy.attempts
+                        && self.is_retryable(&err)
+                        && self.is_autocommit().await? =>
+                {
+                    tokio::time::sleep(delay).await;
+                    delay = delay.saturating_mul(policy.backoff);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
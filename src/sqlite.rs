@@ -1,36 +1,202 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::future::Future;
+use std::hash::{BuildHasher, Hash};
+use std::io::Read;
 use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::panic::AssertUnwindSafe;
 use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use tokio::sync::oneshot;
+use rusqlite::backup::{Backup, StepResult};
+use rusqlite::types::FromSql;
+#[cfg(feature = "load_extension")]
+use rusqlite::LoadExtensionGuard;
+use rusqlite::{params_from_iter, ErrorCode, OptionalExtension};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::runtime::RuntimeFlavor;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
 
-use super::connection::{ConnectionHandle, ConnectionTask};
-use super::query::QueryHandle;
+use super::blob::{BlobHandle, BlobLocation};
+#[cfg(feature = "sqlcipher")]
+use super::cipher::SecretKey;
+use super::connection::{ConnectionHandle, ConnectionTask, WorkerHandle};
+use super::csv::{csv_field, CsvOptions};
+use super::dump::{blocking_dump, is_complete_statement, is_transaction_statement};
+use super::error::Error;
+use super::options::{ConnectionBuilder, ConnectionOptions, RetryPredicate};
+use super::query::{check_arguments, execute_many, insert_many, Arguments, QueryHandle};
 use super::transaction::TransactionHandle;
-
-pub type Error = rusqlite::Error;
+#[cfg(feature = "serde_json")]
+use super::value::json_value;
+use super::value::{duration_from_value, DurationUnit, FromRow, FromValue};
+use super::watchdog::Watchdog;
 
 pub type Value = rusqlite::types::Value;
 
+#[cfg(feature = "hooks")]
+pub use rusqlite::hooks::Action;
+pub use rusqlite::limits::Limit;
+pub use rusqlite::types::{FromSqlError, FromSqlResult};
+pub use rusqlite::{OpenFlags, TransactionBehavior};
+
+/// A boxed future returned by closures of scoped operations.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
 #[derive(Clone, Debug)]
 pub struct Row {
+    pub(super) columns: Arc<[String]>,
     pub(super) values: Vec<Value>,
 }
 
 impl Row {
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
     pub fn values(&self) -> &[Value] {
         &self.values
     }
 
+    /// Returns the value of the column at the index converted to the type.
+    pub fn get<T: FromValue>(&self, index: usize) -> Result<T, Error> {
+        self.convert(index, T::from_value)
+    }
+
+    /// Returns the duration stored in the column at the index as an integer
+    /// number of the units.
+    ///
+    /// See [`duration_value`](crate::duration_value).
+    pub fn get_duration(&self, index: usize, unit: DurationUnit) -> Result<Duration, Error> {
+        self.convert(index, |value| duration_from_value(value, unit))
+    }
+
+    fn convert<T, F>(&self, index: usize, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&Value) -> FromSqlResult<T>,
+    {
+        let value = self
+            .values
+            .get(index)
+            .ok_or(rusqlite::Error::InvalidColumnIndex(index))?;
+        f(value).map_err(|err| {
+            let err = match err {
+                FromSqlError::InvalidType => rusqlite::Error::InvalidColumnType(
+                    index,
+                    self.columns[index].clone(),
+                    value.data_type(),
+                ),
+                FromSqlError::OutOfRange(v) => rusqlite::Error::IntegralValueOutOfRange(index, v),
+                FromSqlError::Other(err) => {
+                    rusqlite::Error::FromSqlConversionFailure(index, value.data_type(), err)
+                }
+                err => rusqlite::Error::FromSqlConversionFailure(
+                    index,
+                    value.data_type(),
+                    Box::new(err),
+                ),
+            };
+            err.into()
+        })
+    }
+
+    /// Returns the value of the column at the index converted to the type,
+    /// or the default if the value is NULL or cannot be converted.
+    pub fn get_or<T: FromValue>(&self, index: usize, default: T) -> T {
+        match self.get::<Option<T>>(index) {
+            Ok(Some(value)) => value,
+            _ => default,
+        }
+    }
+
+    /// Returns the value of the column with the name converted to the type.
+    ///
+    /// Returns an error if there is no column with the name.
+    pub fn get_by_name<T: FromValue>(&self, name: &str) -> Result<T, Error> {
+        let index = self
+            .columns
+            .iter()
+            .position(|v| v == name)
+            .ok_or_else(|| rusqlite::Error::InvalidColumnName(name.to_owned()))?;
+        self.get(index)
+    }
+
     pub fn into_values(self) -> Vec<Value> {
         self.values
     }
+
+    /// Converts the row into a JSON object keyed by column names.
+    ///
+    /// Integers and reals become numbers, texts become strings and NULL
+    /// becomes `null`. Blobs become strings encoded with the standard base64
+    /// alphabet with padding. Non-finite reals become `null`.
+    #[cfg(feature = "serde_json")]
+    pub fn to_json_object(&self) -> serde_json::Value {
+        let object = self
+            .columns
+            .iter()
+            .zip(&self.values)
+            .map(|(column, value)| (column.clone(), json_value(value)))
+            .collect();
+        serde_json::Value::Object(object)
+    }
+}
+
+/// Timings of the command processed by the connection worker.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct QueryMetrics {
+    pub(super) queue_wait: Duration,
+}
+
+impl QueryMetrics {
+    /// Returns the time the command waited for the worker to start processing
+    /// it, for example behind a slow command sent earlier.
+    pub fn queue_wait(&self) -> Duration {
+        self.queue_wait
+    }
+}
+
+/// Properties of a prepared statement.
+#[derive(Clone, Copy, Debug)]
+pub struct StatementInfo {
+    pub(super) readonly: bool,
+    pub(super) column_count: usize,
+    pub(super) parameter_count: usize,
+    pub(super) explain: bool,
+}
+
+impl StatementInfo {
+    /// Returns true if the statement does not write to the database.
+    pub fn readonly(&self) -> bool {
+        self.readonly
+    }
+
+    pub fn column_count(&self) -> usize {
+        self.column_count
+    }
+
+    pub fn parameter_count(&self) -> usize {
+        self.parameter_count
+    }
+
+    /// Returns true if the statement is an `EXPLAIN` or `EXPLAIN QUERY PLAN`.
+    pub fn explain(&self) -> bool {
+        self.explain
+    }
 }
 
 /// A result of executing the statement without the resulting query rows.
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Debug)]
 pub struct Status {
     pub(super) rows_affected: usize,
     pub(super) last_insert_id: Option<i64>,
+    pub(super) metrics: QueryMetrics,
 }
 
 impl Status {
@@ -41,6 +207,10 @@ impl Status {
     pub fn last_insert_id(&self) -> Option<i64> {
         self.last_insert_id
     }
+
+    pub fn metrics(&self) -> QueryMetrics {
+        self.metrics
+    }
 }
 
 /// An asynchronous stream of resulting query rows.
@@ -54,18 +224,63 @@ impl<'a> Rows<'a> {
         self.handle.columns()
     }
 
+    /// Returns the number of columns of the resulting rows.
+    pub fn column_count(&self) -> usize {
+        self.handle.columns().len()
+    }
+
+    /// Returns the declared type of the column at the index.
+    ///
+    /// Returns `None` if the column is an expression rather than a table
+    /// column, or if the index is out of range.
+    pub fn column_decltype(&self, index: usize) -> Option<&str> {
+        self.handle.decltypes().get(index)?.as_deref()
+    }
+
+    pub fn metrics(&self) -> QueryMetrics {
+        self.handle.metrics()
+    }
+
+    /// Returns the number of changed rows and the last inserted rowid once
+    /// all rows have been read, for example of `INSERT ... RETURNING`.
+    /// Readonly queries report no changed rows and no inserted rowid.
+    ///
+    /// Returns `None` while rows remain to be read or if reading them failed.
+    pub fn status(&mut self) -> Option<Status> {
+        self.handle.status()
+    }
+
     pub async fn next(&mut self) -> Option<Result<Row, Error>> {
         self.handle.next().await
     }
+
+    /// Converts the remaining rows into a stream of JSON objects.
+    ///
+    /// See [`Row::to_json_object`].
+    #[cfg(feature = "serde_json")]
+    pub fn json_objects(mut self) -> impl Stream<Item = Result<serde_json::Value, Error>> + 'a {
+        use tokio_stream::StreamExt;
+
+        self.handle
+            .take_stream()
+            .map(|row| row.map(|row| row.to_json_object()))
+    }
 }
 
+/// Stops reading rows, so the worker resets the statement without stepping
+/// the remaining rows.
 impl<'a> Drop for Rows<'a> {
-    fn drop(&mut self) {}
+    fn drop(&mut self) {
+        self.handle.close();
+    }
 }
 
 /// An asynchronous SQLite database transaction.
 pub struct Transaction<'a> {
     tx: TransactionHandle,
+    savepoints: usize,
+    auto_commit_interval: Option<Duration>,
+    committed: Instant,
     _phantom: PhantomData<&'a ()>,
 }
 
@@ -80,13 +295,61 @@ impl<'a> Transaction<'a> {
         self.tx.rollback().await
     }
 
+    /// Commits the changes and begins a new transaction after writes once
+    /// the interval has elapsed since the last commit.
+    ///
+    /// Bounds the amount of work lost on failure during long ingestion,
+    /// while keeping the throughput of batched writes. Committed changes are
+    /// not rolled back when the transaction is dropped. Nothing is committed
+    /// while a savepoint is open. If a new transaction cannot be begun after
+    /// the commit, the transaction is finished and later calls fail with
+    /// [`Error::WorkerClosed`].
+    pub fn auto_commit_interval(&mut self, interval: Duration) {
+        self.auto_commit_interval = Some(interval);
+        self.committed = Instant::now();
+    }
+
+    /// Restarts the transaction if the auto commit interval has elapsed.
+    async fn auto_commit(&mut self) -> Result<(), Error> {
+        let Some(interval) = self.auto_commit_interval else {
+            return Ok(());
+        };
+        if self.savepoints == 0 && self.committed.elapsed() >= interval {
+            self.tx.restart().await?;
+            self.committed = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Starts a savepoint that can be rolled back without discarding the
+    /// rest of the transaction.
+    ///
+    /// The savepoint dereferences to the transaction, so savepoints can be
+    /// nested. Dropping the savepoint without committing rolls it back.
+    pub async fn savepoint(&mut self) -> Result<Savepoint<'_, 'a>, Error> {
+        let name = format!("tokio_sqlite_{}", self.savepoints);
+        self.tx.execute_batch(format!("SAVEPOINT {name}")).await?;
+        self.savepoints += 1;
+        Ok(Savepoint {
+            transaction: self,
+            name,
+            finished: false,
+        })
+    }
+
     /// Executes a statement that does not return the resulting rows.
     ///
     /// Returns an error if the query returns resulting rows.
     pub async fn execute(&mut self, statement: &str, arguments: &[Value]) -> Result<Status, Error> {
-        self.tx
-            .execute(statement.to_owned(), arguments.to_owned())
-            .await
+        let status = self
+            .tx
+            .execute(
+                statement.to_owned(),
+                Arguments::Positional(arguments.to_owned()),
+            )
+            .await?;
+        self.auto_commit().await?;
+        Ok(status)
     }
 
     /// Executes a statement that returns the resulting query rows.
@@ -95,7 +358,160 @@ impl<'a> Transaction<'a> {
         S: Into<String>,
         A: Into<Vec<Value>>,
     {
-        let handle = self.tx.query(statement.into(), arguments.into()).await?;
+        let handle = self
+            .tx
+            .query(statement.into(), Arguments::Positional(arguments.into()), 1)
+            .await?;
+        Ok(Rows {
+            handle,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Executes a statement that returns the resulting query rows, letting
+    /// the worker read up to `capacity` rows ahead of the consumer.
+    ///
+    /// The worker waits once the buffer is full, so a larger capacity trades
+    /// memory for fewer round-trips without reading the whole result.
+    pub async fn query_buffered<S, A>(
+        &mut self,
+        statement: S,
+        arguments: A,
+        capacity: usize,
+    ) -> Result<Rows, Error>
+    where
+        S: Into<String>,
+        A: Into<Vec<Value>>,
+    {
+        let handle = self
+            .tx
+            .query(
+                statement.into(),
+                Arguments::Positional(arguments.into()),
+                capacity,
+            )
+            .await?;
+        Ok(Rows {
+            handle,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Returns true if the connection is in autocommit mode.
+    ///
+    /// See [`Connection::is_autocommit`].
+    pub async fn is_autocommit(&mut self) -> Result<bool, Error> {
+        self.tx.call(|conn| Ok(conn.is_autocommit())).await
+    }
+
+    /// Runs the closure with raw access to the connection on the worker.
+    ///
+    /// The closure runs at the current nesting level, so called on a
+    /// [`Savepoint`] all changes belong to that savepoint.
+    pub async fn with_raw<F, T>(&mut self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&rusqlite::Connection) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.tx.call(f).await
+    }
+
+    /// Executes the statement once for every argument set.
+    ///
+    /// See [`Connection::execute_many`].
+    pub async fn execute_many<S, I>(&mut self, statement: S, arguments: I) -> Result<usize, Error>
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = Vec<Value>>,
+    {
+        let statement = statement.into();
+        let arguments: Vec<_> = arguments.into_iter().collect();
+        let rows_affected = self
+            .tx
+            .call(move |conn| execute_many(conn, &statement, arguments))
+            .await?;
+        self.auto_commit().await?;
+        Ok(rows_affected)
+    }
+
+    /// Executes the statement once for every row of the columnar arguments.
+    ///
+    /// Each inner vector holds the values of one parameter, so row `i` binds
+    /// the `i`-th value of every column. Returns the total number of affected
+    /// rows and the last inserted rowid.
+    pub async fn execute_many_columnar<S>(
+        &mut self,
+        statement: S,
+        columns: Vec<Vec<Value>>,
+    ) -> Result<Status, Error>
+    where
+        S: Into<String>,
+    {
+        let statement = statement.into();
+        let rows = columns.first().map_or(0, |v| v.len());
+        for column in &columns {
+            if column.len() != rows {
+                return Err(Error::ColumnLengthMismatch {
+                    expected: rows,
+                    actual: column.len(),
+                });
+            }
+        }
+        let status = self
+            .tx
+            .call(move |conn| {
+                check_arguments(conn, columns.iter().flatten())?;
+                let mut stmt = conn.prepare(&statement)?;
+                let mut status = Status::default();
+                for i in 0..rows {
+                    let arguments = columns.iter().map(|column| &column[i]);
+                    status.rows_affected += stmt.execute(params_from_iter(arguments))?;
+                    status.last_insert_id = Some(conn.last_insert_rowid());
+                }
+                Ok(status)
+            })
+            .await?;
+        self.auto_commit().await?;
+        Ok(status)
+    }
+
+    /// Executes multiple semicolon-separated statements.
+    pub async fn execute_batch(&mut self, statement: &str) -> Result<(), Error> {
+        self.tx.execute_batch(statement.to_owned()).await?;
+        self.auto_commit().await
+    }
+
+    /// Executes a statement with named parameters that does not return the
+    /// resulting rows.
+    ///
+    /// Returns an error if the query returns resulting rows.
+    pub async fn execute_named(
+        &mut self,
+        statement: &str,
+        arguments: &[(&str, Value)],
+    ) -> Result<Status, Error> {
+        let status = self
+            .tx
+            .execute(statement.to_owned(), named_arguments(arguments))
+            .await?;
+        self.auto_commit().await?;
+        Ok(status)
+    }
+
+    /// Executes a statement with named parameters that returns the resulting
+    /// query rows.
+    pub async fn query_named<S>(
+        &mut self,
+        statement: S,
+        arguments: &[(&str, Value)],
+    ) -> Result<Rows, Error>
+    where
+        S: Into<String>,
+    {
+        let handle = self
+            .tx
+            .query(statement.into(), named_arguments(arguments), 1)
+            .await?;
         Ok(Rows {
             handle,
             _phantom: PhantomData,
@@ -104,7 +520,7 @@ impl<'a> Transaction<'a> {
 
     /// Executes a statement that returns zero or one resulting query row.
     ///
-    /// Returns an error if the query returns more than one row.
+    /// Returns [`Error::MultipleRows`] if the query returns more than one row.
     pub async fn query_row<S, A>(
         &mut self,
         statement: S,
@@ -120,69 +536,164 @@ impl<'a> Transaction<'a> {
             None => return Ok(None),
         };
         if let Some(_) = rows.next().await {
-            return Err(Error::QueryReturnedNoRows);
+            return Err(Error::MultipleRows);
         }
         Ok(Some(row))
     }
+
+    /// Executes a statement and returns the first column of the single
+    /// resulting row converted to the type.
+    ///
+    /// Returns `None` if there are no rows and [`Error::MultipleRows`] if the
+    /// query returns more than one row.
+    pub async fn query_scalar<T, S, A>(
+        &mut self,
+        statement: S,
+        arguments: A,
+    ) -> Result<Option<T>, Error>
+    where
+        T: FromValue,
+        S: Into<String>,
+        A: Into<Vec<Value>>,
+    {
+        match self.query_row(statement, arguments).await? {
+            Some(row) => row.get(0).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Executes a statement and collects all resulting query rows.
+    ///
+    /// Returns the first error encountered while reading the rows.
+    pub async fn query_all<S, A>(&mut self, statement: S, arguments: A) -> Result<Vec<Row>, Error>
+    where
+        S: Into<String>,
+        A: Into<Vec<Value>>,
+    {
+        let mut rows = self.query(statement, arguments).await?;
+        let mut values = Vec::new();
+        while let Some(row) = rows.next().await {
+            values.push(row?);
+        }
+        Ok(values)
+    }
+
+    /// Executes a statement and converts all resulting query rows to the type.
+    pub async fn query_as<T, S, A>(&mut self, statement: S, arguments: A) -> Result<Vec<T>, Error>
+    where
+        T: FromRow,
+        S: Into<String>,
+        A: Into<Vec<Value>>,
+    {
+        let mut rows = self.query(statement, arguments).await?;
+        let mut values = Vec::new();
+        while let Some(row) = rows.next().await {
+            values.push(T::from_row(&row?)?);
+        }
+        Ok(values)
+    }
 }
 
 impl<'a> Drop for Transaction<'a> {
     fn drop(&mut self) {}
 }
 
-/// An asynchronous SQLite client.
-pub struct Connection {
-    tx: Option<ConnectionHandle>,
-    handle: Option<tokio::task::JoinHandle<()>>,
+/// A savepoint inside of a transaction.
+pub struct Savepoint<'t, 'a> {
+    transaction: &'t mut Transaction<'a>,
+    name: String,
+    finished: bool,
 }
 
-impl Connection {
-    /// Opens a new connection to a SQLite database.
-    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-        let task = ConnectionTask::new(path.as_ref().to_owned());
-        let (tx, rx) = oneshot::channel();
-        let handle = tokio::task::spawn_blocking(|| task.blocking_run(tx));
-        Ok(Connection {
-            tx: Some(rx.await.unwrap()?),
-            handle: Some(handle),
-        })
+impl<'t, 'a> Savepoint<'t, 'a> {
+    /// Releases the savepoint, keeping all changes made within it as part of
+    /// the transaction.
+    pub async fn commit(mut self) -> Result<(), Error> {
+        self.finish();
+        let statement = format!("RELEASE {}", self.name);
+        self.transaction.tx.execute_batch(statement).await
     }
 
-    /// Begins new transaction.
-    pub async fn transaction(&mut self) -> Result<Transaction, Error> {
-        let tx = self.tx.as_mut().unwrap().transaction().await?;
-        Ok(Transaction {
-            tx,
-            _phantom: PhantomData,
-        })
+    /// Rolls the savepoint back, discarding all changes made within it.
+    pub async fn rollback(mut self) -> Result<(), Error> {
+        self.finish();
+        let statement = format!("ROLLBACK TO {0}; RELEASE {0}", self.name);
+        self.transaction.tx.execute_batch(statement).await
     }
 
-    /// Executes a statement that does not return the resulting rows.
+    fn finish(&mut self) {
+        self.finished = true;
+        self.transaction.savepoints -= 1;
+    }
+}
+
+impl<'t, 'a> Deref for Savepoint<'t, 'a> {
+    type Target = Transaction<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        self.transaction
+    }
+}
+
+impl<'t, 'a> DerefMut for Savepoint<'t, 'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.transaction
+    }
+}
+
+impl<'t, 'a> Drop for Savepoint<'t, 'a> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.finish();
+            let statement = format!("ROLLBACK TO {0}; RELEASE {0}", self.name);
+            self.transaction.tx.defer_batch(statement);
+        }
+    }
+}
+
+/// A statement prepared by the connection worker.
+///
+/// The worker keeps the prepared statement in the statement cache of the
+/// connection, so it is reused by every execution.
+pub struct Statement<'a> {
+    tx: &'a mut ConnectionHandle,
+    statement: String,
+}
+
+impl<'a> Statement<'a> {
+    /// Returns the SQL text of the statement.
+    pub fn sql(&self) -> &str {
+        &self.statement
+    }
+
+    /// Executes the statement with the arguments.
     ///
     /// Returns an error if the query returns resulting rows.
-    pub async fn execute<S, A>(&mut self, statement: S, arguments: A) -> Result<Status, Error>
+    pub async fn execute<A>(&mut self, arguments: A) -> Result<Status, Error>
     where
-        S: Into<String>,
         A: Into<Vec<Value>>,
     {
         self.tx
-            .as_mut()
-            .unwrap()
-            .execute(statement.into(), arguments.into())
+            .execute(
+                self.statement.clone(),
+                Arguments::Positional(arguments.into()),
+            )
             .await
     }
 
-    /// Executes a statement that returns the resulting query rows.
-    pub async fn query<S, A>(&mut self, statement: S, arguments: A) -> Result<Rows, Error>
+    /// Executes the statement with the arguments and returns the resulting
+    /// query rows.
+    pub async fn query<A>(&mut self, arguments: A) -> Result<Rows, Error>
     where
-        S: Into<String>,
         A: Into<Vec<Value>>,
     {
         let handle = self
             .tx
-            .as_mut()
-            .unwrap()
-            .query(statement.into(), arguments.into())
+            .query(
+                self.statement.clone(),
+                Arguments::Positional(arguments.into()),
+                1,
+            )
             .await?;
         Ok(Rows {
             handle,
@@ -190,36 +701,1516 @@ impl Connection {
         })
     }
 
-    /// Executes a statement that returns zero or one resulting query row.
+    /// Resets the statement and clears the bound parameters, keeping it
+    /// prepared for the next execution.
     ///
-    /// Returns an error if the query returns more than one row.
-    pub async fn query_row<S, A>(
-        &mut self,
-        statement: S,
-        arguments: A,
-    ) -> Result<Option<Row>, Error>
-    where
-        S: Into<String>,
-        A: Into<Vec<Value>>,
-    {
-        let mut rows = self.query(statement, arguments).await?;
-        let row = match rows.next().await {
-            Some(v) => v?,
-            None => return Ok(None),
-        };
-        if let Some(_) = rows.next().await {
-            return Err(Error::QueryReturnedNoRows);
-        }
-        Ok(Some(row))
+    /// The worker resets the statement after every execution, so this is
+    /// needed only to make the state explicit between batches. A statement
+    /// evicted from the cache is prepared again.
+    pub async fn reset(&mut self) -> Result<(), Error> {
+        let sql = self.statement.clone();
+        self.tx
+            .call(move |conn| {
+                let mut stmt = conn.prepare_cached(&sql)?;
+                // Dropping rows that were never stepped only resets the
+                // statement.
+                drop(stmt.raw_query());
+                stmt.clear_bindings();
+                Ok(())
+            })
+            .await
+    }
+
+    /// Finalizes the statement, removing it from the statement cache of the
+    /// connection.
+    pub async fn finalize(self) -> Result<(), Error> {
+        let sql = self.statement;
+        self.tx
+            .call(move |conn| {
+                // Taking the statement out of the cache and discarding it
+                // finalizes it instead of returning it to the cache.
+                conn.prepare_cached(&sql)?.discard();
+                Ok(())
+            })
+            .await
     }
 }
 
-impl Drop for Connection {
-    fn drop(&mut self) {
-        drop(self.tx.take());
-        if let Some(handle) = self.handle.take() {
-            let _ =
-                tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(handle));
-        };
+/// A handle to interrupt operations running on the connection worker.
+///
+/// The handle can be cloned and used from other tasks while the connection
+/// is busy. It remains valid after the connection is closed.
+#[derive(Clone)]
+pub struct InterruptHandle {
+    handle: Arc<rusqlite::InterruptHandle>,
+}
+
+impl InterruptHandle {
+    /// Interrupts the statement currently running on the worker, which fails
+    /// with `SQLITE_INTERRUPT`.
+    ///
+    /// Does nothing if no statement is running.
+    pub fn interrupt(&self) {
+        self.handle.interrupt();
+    }
+}
+
+/// A handle for incremental I/O on a blob column value.
+///
+/// The connection worker is busy serving the blob until the handle is
+/// dropped. The size of the blob is fixed when it is opened, so writes
+/// cannot extend it.
+pub struct Blob<'a> {
+    handle: BlobHandle,
+    _phantom: PhantomData<&'a mut Connection>,
+}
+
+impl<'a> Blob<'a> {
+    /// Returns the size of the blob in bytes.
+    pub async fn size(&mut self) -> Result<usize, Error> {
+        self.handle.size().await
     }
+
+    /// Reads bytes of the blob starting at the offset into the buffer.
+    ///
+    /// Returns the number of bytes read, which is less than the length of
+    /// the buffer if the read reaches the end of the blob.
+    pub async fn read_at(&mut self, buf: &mut [u8], offset: usize) -> Result<usize, Error> {
+        let data = self.handle.read(offset, buf.len()).await?;
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+
+    /// Writes the bytes into the blob starting at the offset.
+    ///
+    /// Returns an error if the write reaches past the end of the blob.
+    pub async fn write_at(&mut self, buf: &[u8], offset: usize) -> Result<(), Error> {
+        self.handle.write(offset, buf.to_vec()).await
+    }
+}
+
+/// An asynchronous SQLite client.
+pub struct Connection {
+    tx: Option<ConnectionHandle>,
+    handle: Option<WorkerHandle>,
+    pub(super) retry_predicate: RetryPredicate,
+    /// Whether the connection was opened with a SQLCipher key.
+    #[cfg(feature = "sqlcipher")]
+    encrypted: bool,
+}
+
+impl Connection {
+    /// Returns a builder to open a new connection with custom options.
+    pub fn builder() -> ConnectionBuilder {
+        ConnectionBuilder::new()
+    }
+
+    /// Opens a new connection to a SQLite database.
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::open_with_flags(path, OpenFlags::default()).await
+    }
+
+    /// Returns true if the database file looks encrypted, for example by
+    /// SQLCipher, and cannot be opened without a key.
+    ///
+    /// Encrypted files have a full 16-byte header that does not match the
+    /// `SQLite format 3` one. Missing, empty and shorter files are not
+    /// encrypted.
+    pub async fn is_encrypted<P: AsRef<Path>>(path: P) -> Result<bool, Error> {
+        const HEADER: &[u8; 16] = b"SQLite format 3\0";
+        let path = path.as_ref().to_owned();
+        tokio::task::spawn_blocking(move || {
+            let file = match std::fs::File::open(path) {
+                Ok(file) => file,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+                Err(err) => return Err(err.into()),
+            };
+            let mut header = Vec::with_capacity(HEADER.len());
+            file.take(HEADER.len() as u64).read_to_end(&mut header)?;
+            Ok(header.len() == HEADER.len() && header != HEADER)
+        })
+        .await
+        .map_err(Error::Join)?
+    }
+
+    /// Opens a new connection to a SQLite database using the flags.
+    pub async fn open_with_flags<P: AsRef<Path>>(path: P, flags: OpenFlags) -> Result<Self, Error> {
+        Self::open_with_options(path, ConnectionOptions::new().flags(flags)).await
+    }
+
+    /// Opens a new connection to a database encrypted by SQLCipher.
+    ///
+    /// The key is applied before any other access to the database. Returns
+    /// an error if the key does not decrypt the database.
+    #[cfg(feature = "sqlcipher")]
+    pub async fn open_encrypted<P: AsRef<Path>>(path: P, key: SecretKey) -> Result<Self, Error> {
+        Self::open_with_options(path, ConnectionOptions::new().key(key)).await
+    }
+
+    /// Opens a new connection to the main database with the reference
+    /// database attached read-only as the `reference` schema.
+    ///
+    /// Unqualified table names are looked up in the main database first and
+    /// then in the reference one, so tables existing only in the reference
+    /// database need no qualification. See [`Connection::qualified_table`].
+    pub async fn with_reference_db<P, R>(main: P, reference: R) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+        R: AsRef<Path>,
+    {
+        let mut conn = Self::open(main).await?;
+        let uri = format!("file:{}?mode=ro", uri_path(reference.as_ref()));
+        conn.execute("ATTACH DATABASE ?1 AS reference", [Value::Text(uri)])
+            .await?;
+        Ok(conn)
+    }
+
+    /// Opens a new connection to a SQLite database using the options.
+    pub async fn open_with_options<P: AsRef<Path>>(
+        path: P,
+        options: ConnectionOptions,
+    ) -> Result<Self, Error> {
+        let watchdog = Watchdog::new(options.watchdog_timeout);
+        let checkpoint_on_idle = options.checkpoint_on_idle;
+        let retry_predicate = options.retry_predicate.clone();
+        #[cfg(feature = "sqlcipher")]
+        let encrypted = options.key.is_some();
+        let task = ConnectionTask::new(path.as_ref().to_owned(), options, watchdog);
+        let (tx, rx) = oneshot::channel();
+        let handle = task.spawn(tx);
+        let conn = rx.await.map_err(|_| Error::WorkerClosed)??;
+        if let Some(idle) = checkpoint_on_idle {
+            conn.spawn_idle_checkpoint(idle);
+        }
+        Ok(Connection {
+            tx: Some(conn),
+            handle: Some(handle),
+            retry_predicate,
+            #[cfg(feature = "sqlcipher")]
+            encrypted,
+        })
+    }
+
+    /// Returns a handle to interrupt queries running on the connection.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle {
+            handle: self.tx.as_ref().unwrap().interrupt_handle(),
+        }
+    }
+
+    /// Changes the key of the database encrypted by SQLCipher in place.
+    ///
+    /// Returns [`Error::NotEncrypted`] if the connection was not opened with
+    /// a key, since SQLCipher cannot encrypt an existing plaintext database
+    /// this way.
+    #[cfg(feature = "sqlcipher")]
+    pub async fn rekey(&mut self, key: SecretKey) -> Result<(), Error> {
+        if !self.encrypted {
+            return Err(Error::NotEncrypted);
+        }
+        self.call_worker(move |conn| key.apply(conn, "rekey")).await
+    }
+
+    /// Runs the closure on the worker with the underlying connection and
+    /// returns its result.
+    ///
+    /// Provides access to features of rusqlite not covered by the wrapper.
+    /// A panic in the closure is caught and returned as [`Error::Panic`],
+    /// keeping the worker alive. A transaction left open by the closure stays
+    /// open on the connection.
+    pub async fn call<F, T>(&mut self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut rusqlite::Connection) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.call_worker(move |conn| {
+            std::panic::catch_unwind(AssertUnwindSafe(|| f(conn))).unwrap_or_else(|payload| {
+                let message = if let Some(message) = payload.downcast_ref::<&str>() {
+                    (*message).to_owned()
+                } else if let Some(message) = payload.downcast_ref::<String>() {
+                    message.clone()
+                } else {
+                    "unknown panic payload".to_owned()
+                };
+                Err(Error::Panic { message })
+            })
+        })
+        .await
+    }
+
+    /// Runs the closure on the worker without catching panics.
+    pub(super) async fn call_worker<F, T>(&mut self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut rusqlite::Connection) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.tx.as_mut().unwrap().call(f).await
+    }
+
+    /// Sets the time to wait for locks held by other connections before
+    /// failing with `SQLITE_BUSY`.
+    ///
+    /// A zero duration disables waiting.
+    pub async fn set_busy_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        self.call_worker(move |conn| Ok(conn.busy_timeout(timeout)?))
+            .await
+    }
+
+    /// Returns true if the connection can no longer process commands,
+    /// because the worker has stopped or did not respond in time.
+    pub fn is_closed(&self) -> bool {
+        self.tx.as_ref().is_none_or(|v| v.is_closed())
+    }
+
+    /// Returns the total number of rows changed by the connection as of the
+    /// last command that the worker replied to, without waiting for it.
+    pub(super) fn changes(&self) -> u64 {
+        self.tx.as_ref().map_or(0, |v| v.changes())
+    }
+
+    /// Sets the number of prepared statements kept in the statement cache.
+    ///
+    /// Statements of all queries are cached, so repeated identical SQL is not
+    /// compiled again. A zero capacity disables the cache.
+    pub async fn set_statement_cache_capacity(&mut self, capacity: usize) -> Result<(), Error> {
+        self.call_worker(move |conn| {
+            conn.set_prepared_statement_cache_capacity(capacity);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Closes the connection and waits for the worker to finish.
+    ///
+    /// Unlike dropping the connection, this does not block the runtime and
+    /// reports the error if the worker failed. Commands that the worker
+    /// exited without processing and that nobody waited for, for example
+    /// because their futures were dropped, are reported as
+    /// [`Error::CommandsLost`] instead.
+    pub async fn close(mut self) -> Result<(), Error> {
+        let poisoned = self.tx.as_ref().is_some_and(|v| v.is_poisoned());
+        let counters = self.tx.as_ref().map(|v| v.counters());
+        drop(self.tx.take());
+        let Some(handle) = self.handle.take() else {
+            return Ok(());
+        };
+        if poisoned {
+            return Err(Error::Poisoned);
+        }
+        let result = handle.join().await;
+        match counters.map_or(0, |v| v.lost()) {
+            0 => result,
+            count => Err(Error::CommandsLost { count }),
+        }
+    }
+
+    /// Begins new transaction.
+    ///
+    /// Returns [`Error::TransactionAlreadyOpen`] if a transaction was already
+    /// started on the connection, for example with a `BEGIN` statement.
+    pub async fn transaction(&mut self) -> Result<Transaction, Error> {
+        self.transaction_with_behavior(TransactionBehavior::Deferred)
+            .await
+    }
+
+    /// Begins new transaction with the behavior.
+    ///
+    /// [`TransactionBehavior::Immediate`] takes the write lock up front, so
+    /// later writes of the transaction do not fail with `SQLITE_BUSY`.
+    pub async fn transaction_with_behavior(
+        &mut self,
+        behavior: TransactionBehavior,
+    ) -> Result<Transaction, Error> {
+        let tx = self.tx.as_mut().unwrap().transaction(behavior).await?;
+        Ok(Transaction {
+            tx,
+            savepoints: 0,
+            auto_commit_interval: None,
+            committed: Instant::now(),
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Starts a read transaction that pins the current snapshot of the
+    /// database.
+    ///
+    /// In WAL mode all following queries see the same snapshot and do not
+    /// observe writes of other connections until [`end_read_snapshot`] is
+    /// called.
+    ///
+    /// [`end_read_snapshot`]: Connection::end_read_snapshot
+    pub async fn begin_read_snapshot(&mut self) -> Result<(), Error> {
+        self.call_worker(|conn| {
+            if !conn.is_autocommit() {
+                return Err(Error::TransactionAlreadyOpen);
+            }
+            conn.execute_batch("BEGIN DEFERRED")?;
+            // The snapshot is taken by the first read of the transaction.
+            if let Err(err) = conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |_| Ok(())) {
+                let _ = conn.execute_batch("ROLLBACK");
+                return Err(err.into());
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Ends the read transaction started by [`begin_read_snapshot`].
+    ///
+    /// [`begin_read_snapshot`]: Connection::begin_read_snapshot
+    pub async fn end_read_snapshot(&mut self) -> Result<(), Error> {
+        self.execute_batch("COMMIT").await
+    }
+
+    /// Runs the closure inside a new transaction.
+    ///
+    /// The transaction is committed when the closure succeeds and rolled back
+    /// when it fails. If the closure panics, the dropped transaction is rolled
+    /// back by the worker.
+    pub async fn transaction_scope<F, T>(&mut self, f: F) -> Result<T, Error>
+    where
+        F: for<'t, 'c> FnOnce(&'t mut Transaction<'c>) -> BoxFuture<'t, Result<T, Error>>,
+    {
+        let mut tx = self.transaction().await?;
+        match f(&mut tx).await {
+            Ok(value) => {
+                tx.commit().await?;
+                Ok(value)
+            }
+            Err(err) => {
+                tx.rollback().await?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Runs the closure inside a savepoint.
+    ///
+    /// The savepoint is released when the closure succeeds and rolled back
+    /// when it fails. Outside of a transaction the savepoint behaves like one.
+    pub async fn with_savepoint<F, T>(&mut self, f: F) -> Result<T, Error>
+    where
+        F: for<'c> FnOnce(&'c mut Connection) -> BoxFuture<'c, Result<T, Error>>,
+    {
+        self.execute("SAVEPOINT tokio_sqlite", []).await?;
+        match f(self).await {
+            Ok(value) => {
+                self.execute("RELEASE tokio_sqlite", []).await?;
+                Ok(value)
+            }
+            Err(err) => {
+                self.execute("ROLLBACK TO tokio_sqlite", []).await?;
+                self.execute("RELEASE tokio_sqlite", []).await?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Prepares the statement for repeated executions.
+    ///
+    /// Returns an error if the statement is not valid.
+    pub async fn prepare(&mut self, statement: &str) -> Result<Statement, Error> {
+        let statement = statement.to_owned();
+        let tx = self.tx.as_mut().unwrap();
+        let sql = statement.clone();
+        tx.call(move |conn| {
+            conn.prepare_cached(&sql)?;
+            Ok(())
+        })
+        .await?;
+        Ok(Statement { tx, statement })
+    }
+
+    /// Executes a statement that does not return the resulting rows.
+    ///
+    /// Returns an error if the query returns resulting rows.
+    pub async fn execute<S, A>(&mut self, statement: S, arguments: A) -> Result<Status, Error>
+    where
+        S: Into<String>,
+        A: Into<Vec<Value>>,
+    {
+        self.tx
+            .as_mut()
+            .unwrap()
+            .execute(statement.into(), Arguments::Positional(arguments.into()))
+            .await
+    }
+
+    /// Executes a statement like [`Connection::execute`], taking the
+    /// arguments from an iterator.
+    ///
+    /// The values are collected once into the command sent to the worker.
+    pub async fn execute_iter<S, I>(&mut self, statement: S, arguments: I) -> Result<Status, Error>
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = Value>,
+    {
+        self.tx
+            .as_mut()
+            .unwrap()
+            .execute(
+                statement.into(),
+                Arguments::Positional(arguments.into_iter().collect()),
+            )
+            .await
+    }
+
+    /// Executes the statement once for every argument set.
+    ///
+    /// The statement is prepared once and all sets are executed in a single
+    /// round-trip to the worker. Returns the total number of affected rows or
+    /// [`Error::ArgumentSet`] with the index of the first failed set. Sets
+    /// executed before the failure are not rolled back outside of a
+    /// transaction.
+    pub async fn execute_many<S, I>(&mut self, statement: S, arguments: I) -> Result<usize, Error>
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = Vec<Value>>,
+    {
+        let statement = statement.into();
+        let arguments: Vec<_> = arguments.into_iter().collect();
+        self.call_worker(move |conn| execute_many(conn, &statement, arguments))
+            .await
+    }
+
+    /// Inserts a row for every argument set and returns the rowids of the
+    /// first and the last inserted rows.
+    ///
+    /// All sets are inserted inside a single immediate transaction, which is
+    /// rolled back with [`Error::ArgumentSet`] if any set fails. Rowids in
+    /// between are only guaranteed to belong to the inserted rows if SQLite
+    /// allocates them sequentially: explicit rowids, or tables whose largest
+    /// rowid is already used, break the range. Returns `(0, 0)` if there are
+    /// no argument sets.
+    pub async fn insert_many<S, I>(&mut self, statement: S, rows: I) -> Result<(i64, i64), Error>
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = Vec<Value>>,
+    {
+        let statement = statement.into();
+        let rows: Vec<_> = rows.into_iter().collect();
+        self.call_worker(move |conn| insert_many(conn, &statement, rows))
+            .await
+    }
+
+    /// Executes multiple semicolon-separated statements.
+    pub async fn execute_batch(&mut self, statement: &str) -> Result<(), Error> {
+        self.tx
+            .as_mut()
+            .unwrap()
+            .execute_batch(statement.to_owned())
+            .await
+    }
+
+    /// Executes a statement with named parameters that does not return the
+    /// resulting rows.
+    ///
+    /// Returns an error if the query returns resulting rows.
+    pub async fn execute_named<S>(
+        &mut self,
+        statement: S,
+        arguments: &[(&str, Value)],
+    ) -> Result<Status, Error>
+    where
+        S: Into<String>,
+    {
+        self.tx
+            .as_mut()
+            .unwrap()
+            .execute(statement.into(), named_arguments(arguments))
+            .await
+    }
+
+    /// Executes a statement that returns the resulting query rows.
+    pub async fn query<S, A>(&mut self, statement: S, arguments: A) -> Result<Rows, Error>
+    where
+        S: Into<String>,
+        A: Into<Vec<Value>>,
+    {
+        let handle = self
+            .tx
+            .as_mut()
+            .unwrap()
+            .query(statement.into(), Arguments::Positional(arguments.into()), 1)
+            .await?;
+        Ok(Rows {
+            handle,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Executes a statement like [`Connection::query`], taking the arguments
+    /// from an iterator.
+    ///
+    /// The values are collected once into the command sent to the worker.
+    pub async fn query_iter<S, I>(&mut self, statement: S, arguments: I) -> Result<Rows, Error>
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = Value>,
+    {
+        let handle = self
+            .tx
+            .as_mut()
+            .unwrap()
+            .query(
+                statement.into(),
+                Arguments::Positional(arguments.into_iter().collect()),
+                1,
+            )
+            .await?;
+        Ok(Rows {
+            handle,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Executes a statement that returns the resulting query rows, letting
+    /// the worker read up to `capacity` rows ahead of the consumer.
+    ///
+    /// The worker waits once the buffer is full, so a larger capacity trades
+    /// memory for fewer round-trips without reading the whole result.
+    pub async fn query_buffered<S, A>(
+        &mut self,
+        statement: S,
+        arguments: A,
+        capacity: usize,
+    ) -> Result<Rows, Error>
+    where
+        S: Into<String>,
+        A: Into<Vec<Value>>,
+    {
+        let handle = self
+            .tx
+            .as_mut()
+            .unwrap()
+            .query(
+                statement.into(),
+                Arguments::Positional(arguments.into()),
+                capacity,
+            )
+            .await?;
+        Ok(Rows {
+            handle,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Executes a statement with named parameters that returns the resulting
+    /// query rows.
+    pub async fn query_named<S>(
+        &mut self,
+        statement: S,
+        arguments: &[(&str, Value)],
+    ) -> Result<Rows, Error>
+    where
+        S: Into<String>,
+    {
+        let handle = self
+            .tx
+            .as_mut()
+            .unwrap()
+            .query(statement.into(), named_arguments(arguments), 1)
+            .await?;
+        Ok(Rows {
+            handle,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Executes a statement that returns zero or one resulting query row.
+    ///
+    /// Returns [`Error::MultipleRows`] if the query returns more than one row.
+    pub async fn query_row<S, A>(
+        &mut self,
+        statement: S,
+        arguments: A,
+    ) -> Result<Option<Row>, Error>
+    where
+        S: Into<String>,
+        A: Into<Vec<Value>>,
+    {
+        let mut rows = self.query(statement, arguments).await?;
+        let row = match rows.next().await {
+            Some(v) => v?,
+            None => return Ok(None),
+        };
+        if let Some(_) = rows.next().await {
+            return Err(Error::MultipleRows);
+        }
+        Ok(Some(row))
+    }
+
+    /// Executes a statement and returns the first column of the single
+    /// resulting row converted to the type.
+    ///
+    /// Returns `None` if there are no rows and [`Error::MultipleRows`] if the
+    /// query returns more than one row.
+    pub async fn query_scalar<T, S, A>(
+        &mut self,
+        statement: S,
+        arguments: A,
+    ) -> Result<Option<T>, Error>
+    where
+        T: FromValue,
+        S: Into<String>,
+        A: Into<Vec<Value>>,
+    {
+        match self.query_row(statement, arguments).await? {
+            Some(row) => row.get(0).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Executes a statement and collects all resulting query rows.
+    ///
+    /// Returns the first error encountered while reading the rows.
+    pub async fn query_all<S, A>(&mut self, statement: S, arguments: A) -> Result<Vec<Row>, Error>
+    where
+        S: Into<String>,
+        A: Into<Vec<Value>>,
+    {
+        let mut rows = self.query(statement, arguments).await?;
+        let mut values = Vec::new();
+        while let Some(row) = rows.next().await {
+            values.push(row?);
+        }
+        Ok(values)
+    }
+
+    /// Executes a statement and converts all resulting query rows to the type.
+    pub async fn query_as<T, S, A>(&mut self, statement: S, arguments: A) -> Result<Vec<T>, Error>
+    where
+        T: FromRow,
+        S: Into<String>,
+        A: Into<Vec<Value>>,
+    {
+        let mut rows = self.query(statement, arguments).await?;
+        let mut values = Vec::new();
+        while let Some(row) = rows.next().await {
+            values.push(T::from_row(&row?)?);
+        }
+        Ok(values)
+    }
+
+    /// Executes a statement and collects every resulting row into a map of
+    /// column names to values.
+    ///
+    /// If several columns have the same name, the last one is kept.
+    pub async fn query_maps<S, A>(
+        &mut self,
+        statement: S,
+        arguments: A,
+    ) -> Result<Vec<HashMap<String, Value>>, Error>
+    where
+        S: Into<String>,
+        A: Into<Vec<Value>>,
+    {
+        let mut rows = self.query(statement, arguments).await?;
+        let mut maps = Vec::new();
+        while let Some(row) = rows.next().await {
+            let row = row?;
+            maps.push(row.columns.iter().cloned().zip(row.values).collect());
+        }
+        Ok(maps)
+    }
+
+    /// Executes a statement and collects the first two columns of the resulting
+    /// rows into a map of keys to values.
+    pub async fn query_map_kv<K, V, S, A>(
+        &mut self,
+        statement: S,
+        arguments: A,
+    ) -> Result<HashMap<K, V>, Error>
+    where
+        K: FromSql + Eq + Hash + Send + 'static,
+        V: FromSql + Send + 'static,
+        S: Into<String>,
+        A: Into<Vec<Value>>,
+    {
+        let statement = statement.into();
+        let arguments = arguments.into();
+        self.call_worker(move |conn| {
+            check_arguments(conn, &arguments)?;
+            let mut stmt = conn.prepare(&statement)?;
+            let mut rows = stmt.query(params_from_iter(arguments))?;
+            let mut map = HashMap::new();
+            while let Some(row) = rows.next()? {
+                map.insert(row.get(0)?, row.get(1)?);
+            }
+            Ok(map)
+        })
+        .await
+    }
+
+    /// Executes a statement and groups the resulting rows by the derived key.
+    pub async fn query_grouped<K, T, S, A, KF, VF>(
+        &mut self,
+        statement: S,
+        arguments: A,
+        mut key_fn: KF,
+        mut val_fn: VF,
+    ) -> Result<HashMap<K, Vec<T>>, Error>
+    where
+        K: Eq + Hash,
+        S: Into<String>,
+        A: Into<Vec<Value>>,
+        KF: FnMut(&Row) -> Result<K, Error>,
+        VF: FnMut(&Row) -> Result<T, Error>,
+    {
+        let mut rows = self.query(statement, arguments).await?;
+        let mut groups: HashMap<K, Vec<T>> = HashMap::new();
+        while let Some(row) = rows.next().await {
+            let row = row?;
+            let key = key_fn(&row)?;
+            groups.entry(key).or_default().push(val_fn(&row)?);
+        }
+        Ok(groups)
+    }
+
+    /// Executes a statement and transforms the resulting rows on the worker.
+    ///
+    /// Only the transformed values are sent back from the worker.
+    pub async fn query_transform<T, F, S, A>(
+        &mut self,
+        statement: S,
+        arguments: A,
+        mut f: F,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: Send + 'static,
+        F: FnMut(&rusqlite::Row) -> Result<T, Error> + Send + 'static,
+        S: Into<String>,
+        A: Into<Vec<Value>>,
+    {
+        let statement = statement.into();
+        let arguments = arguments.into();
+        self.call_worker(move |conn| {
+            check_arguments(conn, &arguments)?;
+            let mut stmt = conn.prepare(&statement)?;
+            let mut rows = stmt.query(params_from_iter(arguments))?;
+            let mut values = Vec::new();
+            while let Some(row) = rows.next()? {
+                values.push(f(row)?);
+            }
+            Ok(values)
+        })
+        .await
+    }
+
+    /// Executes a statement and writes the resulting query rows to the
+    /// writer as CSV.
+    ///
+    /// Rows are written while they are read, so the result is never buffered
+    /// as a whole.
+    pub async fn query_to_csv<S, A, W>(
+        &mut self,
+        statement: S,
+        arguments: A,
+        mut writer: W,
+        options: CsvOptions,
+    ) -> Result<(), Error>
+    where
+        S: Into<String>,
+        A: Into<Vec<Value>>,
+        W: AsyncWrite + Unpin,
+    {
+        let mut rows = self.query(statement, arguments).await?;
+        if options.header {
+            let record = options.record(rows.columns().iter().map(String::as_str));
+            writer.write_all(record.as_bytes()).await?;
+        }
+        while let Some(row) = rows.next().await {
+            let fields: Vec<_> = row?.values().iter().map(csv_field).collect();
+            let record = options.record(fields.iter().map(String::as_str));
+            writer.write_all(record.as_bytes()).await?;
+        }
+        writer.flush().await?;
+        Ok(())
+    }
+
+    /// Executes a statement taken from the statement cache of the worker.
+    pub(super) async fn execute_cached(
+        &mut self,
+        statement: String,
+        arguments: Vec<Value>,
+    ) -> Result<Status, Error> {
+        let enqueued = Instant::now();
+        self.call_worker(move |conn| {
+            let metrics = QueryMetrics {
+                queue_wait: enqueued.elapsed(),
+            };
+            check_arguments(conn, &arguments)?;
+            let mut stmt = conn.prepare_cached(&statement)?;
+            let rows_affected = stmt.execute(params_from_iter(arguments))?;
+            Ok(Status {
+                rows_affected,
+                last_insert_id: Some(conn.last_insert_rowid()),
+                metrics,
+            })
+        })
+        .await
+    }
+
+    /// Executes a statement taken from the statement cache of the worker
+    /// and transforms the resulting rows on the worker.
+    pub(super) async fn query_cached<T, F>(
+        &mut self,
+        statement: String,
+        arguments: Vec<Value>,
+        mut f: F,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: Send + 'static,
+        F: FnMut(&rusqlite::Row) -> Result<T, Error> + Send + 'static,
+    {
+        self.call_worker(move |conn| {
+            check_arguments(conn, &arguments)?;
+            let mut stmt = conn.prepare_cached(&statement)?;
+            let mut rows = stmt.query(params_from_iter(arguments))?;
+            let mut values = Vec::new();
+            while let Some(row) = rows.next()? {
+                values.push(f(row)?);
+            }
+            Ok(values)
+        })
+        .await
+    }
+
+    /// Returns a stream of the rows of the table expression paired with their
+    /// rowids.
+    ///
+    /// The table expression follows `FROM` in `SELECT rowid, * FROM ...`, so
+    /// it may contain a `WHERE` clause using the arguments. The rowid is not
+    /// included in the returned rows. Returns an error if the table is a
+    /// `WITHOUT ROWID` table.
+    pub async fn query_with_rowid<S, A>(
+        &mut self,
+        table_sql: S,
+        arguments: A,
+    ) -> Result<impl Stream<Item = Result<(i64, Row), Error>> + '_, Error>
+    where
+        S: AsRef<str>,
+        A: Into<Vec<Value>>,
+    {
+        use tokio_stream::StreamExt;
+
+        let statement = format!("SELECT rowid, * FROM {}", table_sql.as_ref());
+        let mut rows = self.query(statement, arguments).await?;
+        let mut columns: Option<Arc<[String]>> = None;
+        Ok(rows.handle.take_stream().map(move |row| {
+            let mut row = row?;
+            let rowid = row.get::<i64>(0)?;
+            row.values.remove(0);
+            // All rows share the columns without the rowid.
+            let columns = columns.get_or_insert_with(|| row.columns[1..].into());
+            row.columns = columns.clone();
+            Ok((rowid, row))
+        }))
+    }
+
+    /// Returns a stream of SQL statements reconstructing the database.
+    ///
+    /// Statements are produced by the worker while the stream is consumed.
+    pub async fn dump(&mut self) -> Result<impl Stream<Item = Result<String, Error>> + '_, Error> {
+        let (tx, rx) = mpsc::channel(1);
+        self.tx
+            .as_mut()
+            .unwrap()
+            .spawn(move |conn| blocking_dump(conn, tx))
+            .await?;
+        Ok(ReceiverStream::new(rx))
+    }
+
+    /// Restores the database from a stream of SQL statements such as
+    /// produced by [`Connection::dump`].
+    ///
+    /// Statements are executed in batches, each inside its own transaction.
+    /// Transaction statements of the dump are skipped and `PRAGMA` statements
+    /// are executed outside of batches.
+    pub async fn restore_dump<R>(&mut self, mut reader: R) -> Result<(), Error>
+    where
+        R: AsyncBufRead + Unpin,
+    {
+        const BATCH_SIZE: usize = 1000;
+        let mut batch = Vec::new();
+        let mut statement = String::new();
+        loop {
+            let len = reader.read_line(&mut statement).await?;
+            if len > 0 && !is_complete_statement(&statement) {
+                continue;
+            }
+            let text = statement.trim();
+            if text.is_empty() {
+                if len == 0 {
+                    break;
+                }
+                statement.clear();
+                continue;
+            }
+            if text
+                .get(..6)
+                .is_some_and(|v| v.eq_ignore_ascii_case("PRAGMA"))
+            {
+                self.restore_batch(std::mem::take(&mut batch)).await?;
+                self.execute_batch(text).await?;
+            } else if !is_transaction_statement(text) {
+                batch.push(text.to_owned());
+                if batch.len() >= BATCH_SIZE {
+                    self.restore_batch(std::mem::take(&mut batch)).await?;
+                }
+            }
+            statement.clear();
+            if len == 0 {
+                break;
+            }
+        }
+        self.restore_batch(batch).await
+    }
+
+    /// Copies the database into the file at the path while the connection
+    /// stays usable by other connections to the database.
+    ///
+    /// Works for in-memory databases, so they can be persisted to disk.
+    pub async fn backup<P: AsRef<Path>>(&mut self, dst: P) -> Result<(), Error> {
+        self.backup_with_progress(dst, |_, _| {}).await
+    }
+
+    /// Same as [`Connection::backup`], but calls the closure with the numbers
+    /// of remaining and total pages after every step of the backup.
+    ///
+    /// The closure runs on the connection worker.
+    pub async fn backup_with_progress<P, F>(&mut self, dst: P, mut progress: F) -> Result<(), Error>
+    where
+        P: AsRef<Path>,
+        F: FnMut(usize, usize) + Send + 'static,
+    {
+        const PAGES_PER_STEP: i32 = 128;
+        const BUSY_PAUSE: Duration = Duration::from_millis(10);
+        let dst = dst.as_ref().to_owned();
+        self.call_worker(move |conn| {
+            let mut dst = rusqlite::Connection::open(dst)?;
+            let backup = Backup::new(conn, &mut dst)?;
+            loop {
+                match backup.step(PAGES_PER_STEP)? {
+                    StepResult::Done => break,
+                    StepResult::Busy | StepResult::Locked => std::thread::sleep(BUSY_PAUSE),
+                    _ => {}
+                }
+                let state = backup.progress();
+                progress(state.remaining as usize, state.pagecount as usize);
+            }
+            let state = backup.progress();
+            progress(state.remaining as usize, state.pagecount as usize);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn restore_batch(&mut self, batch: Vec<String>) -> Result<(), Error> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.call_worker(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute_batch(&batch.join("\n"))?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Opens the blob stored in the column of the row for incremental I/O.
+    ///
+    /// The database is `main`, `temp` or the name of an attached database.
+    pub async fn blob_open(
+        &mut self,
+        db: &str,
+        table: &str,
+        column: &str,
+        rowid: i64,
+        read_only: bool,
+    ) -> Result<Blob, Error> {
+        let location = BlobLocation {
+            db: db.to_owned(),
+            table: table.to_owned(),
+            column: column.to_owned(),
+            rowid,
+            read_only,
+        };
+        let handle = self.tx.as_mut().unwrap().blob_open(location).await?;
+        Ok(Blob {
+            handle,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Prepares the statement and returns its properties in a single
+    /// round-trip.
+    pub async fn statement_info(&mut self, statement: &str) -> Result<StatementInfo, Error> {
+        let statement = statement.to_owned();
+        self.call_worker(move |conn| {
+            let stmt = conn.prepare_cached(&statement)?;
+            Ok(StatementInfo {
+                readonly: stmt.readonly(),
+                column_count: stmt.column_count(),
+                parameter_count: stmt.parameter_count(),
+                explain: is_explain_statement(&statement),
+            })
+        })
+        .await
+    }
+
+    /// Returns the names of the statement parameters in the order of their
+    /// indexes.
+    ///
+    /// Positional parameters such as `?` have no name.
+    pub async fn parameter_names(&mut self, statement: &str) -> Result<Vec<Option<String>>, Error> {
+        let statement = statement.to_owned();
+        self.call_worker(move |conn| {
+            let stmt = conn.prepare(&statement)?;
+            Ok((1..=stmt.parameter_count())
+                .map(|i| stmt.parameter_name(i).map(str::to_owned))
+                .collect())
+        })
+        .await
+    }
+
+    /// Returns the current value of the run-time limit.
+    pub async fn limit(&mut self, limit: Limit) -> Result<i32, Error> {
+        self.call_worker(move |conn| Ok(conn.limit(limit))).await
+    }
+
+    /// Changes the run-time limit and returns its previous value.
+    ///
+    /// Text and blob arguments longer than [`Limit::SQLITE_LIMIT_LENGTH`] are
+    /// rejected with [`Error::ValueTooLarge`].
+    pub async fn set_limit(&mut self, limit: Limit, value: i32) -> Result<i32, Error> {
+        self.call_worker(move |conn| Ok(conn.set_limit(limit, value)))
+            .await
+    }
+
+    /// Returns the schema version, which SQLite increments on every schema
+    /// change.
+    ///
+    /// Can be used to invalidate cached statements and metadata.
+    pub async fn schema_version(&mut self) -> Result<i32, Error> {
+        self.call_worker(|conn| {
+            Ok(conn.pragma_query_value(None, "schema_version", |row| row.get(0))?)
+        })
+        .await
+    }
+
+    /// Returns the user version stored in the database header.
+    ///
+    /// SQLite does not use the value itself, it is commonly used to track
+    /// the version of the schema for migrations.
+    pub async fn user_version(&mut self) -> Result<i32, Error> {
+        self.call_worker(
+            |conn| Ok(conn.pragma_query_value(None, "user_version", |row| row.get(0))?),
+        )
+        .await
+    }
+
+    /// Sets the user version stored in the database header.
+    pub async fn set_user_version(&mut self, version: i32) -> Result<(), Error> {
+        self.call_worker(move |conn| Ok(conn.pragma_update(None, "user_version", version)?))
+            .await
+    }
+
+    /// Returns the application id stored in the database header.
+    pub async fn application_id(&mut self) -> Result<i32, Error> {
+        self.call_worker(|conn| {
+            Ok(conn.pragma_query_value(None, "application_id", |row| row.get(0))?)
+        })
+        .await
+    }
+
+    /// Sets the application id identifying the file format of the database.
+    pub async fn set_application_id(&mut self, id: i32) -> Result<(), Error> {
+        self.call_worker(move |conn| Ok(conn.pragma_update(None, "application_id", id)?))
+            .await
+    }
+
+    /// Installs a hook reporting the database name and the number of frames
+    /// in the write-ahead log after every commit in WAL mode.
+    ///
+    /// The reports are sent to the returned channel, replacing the channel
+    /// of the previously installed hook. The hook replaces the automatic
+    /// checkpoints of SQLite, so the consumer is responsible for
+    /// checkpointing the log.
+    #[cfg(feature = "hooks")]
+    pub async fn wal_hook(&mut self) -> Result<mpsc::UnboundedReceiver<(String, u32)>, Error> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.tx.as_mut().unwrap().wal_hook(tx).await?;
+        Ok(rx)
+    }
+
+    /// Registers the callback invoked with the action, the database name,
+    /// the table name and the rowid of every inserted, updated or deleted
+    /// row, replacing the previous one.
+    ///
+    /// The callback runs on the connection worker, events can be delivered
+    /// to async code through a channel. `None` removes the callback.
+    #[cfg(feature = "hooks")]
+    pub async fn update_hook<F>(&mut self, hook: Option<F>) -> Result<(), Error>
+    where
+        F: FnMut(Action, &str, &str, i64) + Send + 'static,
+    {
+        self.call_worker(move |conn| {
+            conn.update_hook(hook);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Registers the callback invoked before every commit, replacing the
+    /// previous one.
+    ///
+    /// If the callback returns true, the commit is turned into a rollback.
+    /// `None` removes the callback.
+    #[cfg(feature = "hooks")]
+    pub async fn commit_hook<F>(&mut self, hook: Option<F>) -> Result<(), Error>
+    where
+        F: FnMut() -> bool + Send + 'static,
+    {
+        self.call_worker(move |conn| {
+            conn.commit_hook(hook);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Registers the callback invoked on every rollback, replacing the
+    /// previous one. `None` removes the callback.
+    #[cfg(feature = "hooks")]
+    pub async fn rollback_hook<F>(&mut self, hook: Option<F>) -> Result<(), Error>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        self.call_worker(move |conn| {
+            conn.rollback_hook(hook);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Registers the handler invoked about every `n_ops` virtual machine
+    /// instructions of running statements, replacing the previous one.
+    ///
+    /// If the handler returns true, the running statement is aborted and
+    /// fails with an interrupt error. The handler runs on the connection
+    /// worker. `None` or a non-positive `n_ops` removes the handler.
+    #[cfg(feature = "hooks")]
+    pub async fn progress_handler<F>(&mut self, n_ops: i32, handler: Option<F>) -> Result<(), Error>
+    where
+        F: FnMut() -> bool + Send + 'static,
+    {
+        let handler = handler.map(|f| {
+            let mut f = AssertUnwindSafe(f);
+            move || (*f)()
+        });
+        self.call_worker(move |conn| {
+            conn.progress_handler(n_ops, handler);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Loads the SQLite extension from the shared library at the path.
+    ///
+    /// Extension loading is enabled only while the library is loaded. The
+    /// entry point is derived from the file name if not given.
+    ///
+    /// # Safety
+    ///
+    /// The extension runs arbitrary code inside the process, so it must be
+    /// trusted. See `rusqlite::Connection::load_extension`.
+    #[cfg(feature = "load_extension")]
+    pub async unsafe fn load_extension<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        entry_point: Option<&str>,
+    ) -> Result<(), Error> {
+        let path = path.as_ref().to_owned();
+        let entry_point = entry_point.map(str::to_owned);
+        self.call_worker(move |conn| {
+            let _guard = unsafe { LoadExtensionGuard::new(conn)? };
+            unsafe { conn.load_extension(path, entry_point.as_deref())? };
+            Ok(())
+        })
+        .await
+    }
+
+    /// Returns true if the connection is in autocommit mode, that is no
+    /// transaction is open.
+    ///
+    /// Can be used to detect transactions left open by raw statements.
+    pub async fn is_autocommit(&mut self) -> Result<bool, Error> {
+        self.call_worker(|conn| Ok(conn.is_autocommit())).await
+    }
+
+    /// Returns the total number of rows inserted, updated or deleted by the
+    /// connection since it was opened.
+    ///
+    /// Rows changed inside a transaction are counted once their statements
+    /// complete, even if the transaction is rolled back later.
+    pub async fn total_changes(&mut self) -> Result<i64, Error> {
+        self.call_worker(|conn| Ok(conn.query_row("SELECT total_changes()", [], |row| row.get(0))?))
+            .await
+    }
+
+    /// Returns the rowid of the row most recently inserted by the connection,
+    /// or zero if no row has been inserted.
+    ///
+    /// Inserts of transactions that were rolled back are not undone.
+    pub async fn last_insert_rowid(&mut self) -> Result<i64, Error> {
+        self.call_worker(|conn| Ok(conn.last_insert_rowid())).await
+    }
+
+    /// Returns the number of frames in the write-ahead log.
+    ///
+    /// The count is read by running a passive checkpoint, which copies frames
+    /// not used by readers into the database without waiting for locks.
+    /// Once the whole log is checkpointed, the next write restarts it from
+    /// the first frame. Returns zero if the database is not in WAL mode.
+    pub async fn wal_frame_count(&mut self) -> Result<u32, Error> {
+        self.call_worker(|conn| {
+            let frames: i64 =
+                conn.query_row("PRAGMA wal_checkpoint(PASSIVE)", [], |row| row.get(1))?;
+            Ok(frames.max(0) as u32)
+        })
+        .await
+    }
+
+    /// Returns the name of the table qualified with the schema it is found
+    /// in, such as `"reference"."countries"`.
+    ///
+    /// Schemas are searched in the same order SQLite resolves unqualified
+    /// names: `main`, `temp` and then attached databases. Returns `None` if
+    /// no schema has the table or view.
+    pub async fn qualified_table(&mut self, table: &str) -> Result<Option<String>, Error> {
+        let table = table.to_owned();
+        self.call_worker(move |conn| {
+            let mut stmt = conn.prepare("PRAGMA database_list")?;
+            let schemas = stmt
+                .query_map([], |row| row.get::<_, String>(1))?
+                .collect::<Result<Vec<_>, _>>()?;
+            for schema in schemas {
+                let schema = quote_identifier(&schema);
+                let found = conn
+                    .query_row(
+                        &format!(
+                            "SELECT 1 FROM {schema}.sqlite_master \
+                                WHERE type IN ('table', 'view') AND name = ?1"
+                        ),
+                        [&table],
+                        |_| Ok(()),
+                    )
+                    .optional()?;
+                if found.is_some() {
+                    return Ok(Some(format!("{schema}.{}", quote_identifier(&table))));
+                }
+            }
+            Ok(None)
+        })
+        .await
+    }
+
+    /// Returns the number of rows of every table of the main database.
+    ///
+    /// Internal `sqlite_*` tables are skipped. All tables are counted in one
+    /// worker call.
+    pub async fn table_row_counts(&mut self) -> Result<HashMap<String, i64>, Error> {
+        self.call_worker(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT name FROM sqlite_master \
+                    WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+            )?;
+            let tables = stmt
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut counts = HashMap::with_capacity(tables.len());
+            for table in tables {
+                let count = conn.query_row(
+                    &format!("SELECT COUNT(*) FROM {}", quote_identifier(&table)),
+                    [],
+                    |row| row.get(0),
+                )?;
+                counts.insert(table, count);
+            }
+            Ok(counts)
+        })
+        .await
+    }
+
+    /// Returns true if the SQLite library provides JSON functions.
+    pub async fn has_json_support(&mut self) -> bool {
+        self.query_row("SELECT json('{}')", []).await.is_ok()
+    }
+
+    /// Extracts a value at the JSON path from the column of every table row.
+    ///
+    /// Wraps the `json_extract` function and requires JSON support.
+    pub async fn query_json_extract(
+        &mut self,
+        table: &str,
+        column: &str,
+        path: &str,
+    ) -> Result<Vec<Value>, Error> {
+        let statement = format!(
+            "SELECT json_extract({}, $1) FROM {}",
+            quote_identifier(column),
+            quote_identifier(table),
+        );
+        let mut rows = self.query(statement, [path.to_owned().into()]).await?;
+        let mut values = Vec::new();
+        while let Some(row) = rows.next().await {
+            values.extend(row?.into_values());
+        }
+        Ok(values)
+    }
+
+    /// Inserts a row using a random positive rowid that is not present in the table.
+    ///
+    /// A new rowid is tried if the chosen one is already taken, up to a few
+    /// times. Other constraint violations are returned without retrying.
+    /// Returns the chosen rowid.
+    pub async fn insert_with_random_rowid<A>(
+        &mut self,
+        table: &str,
+        columns: &[&str],
+        values: A,
+    ) -> Result<i64, Error>
+    where
+        A: Into<Vec<Value>>,
+    {
+        const MAX_ATTEMPTS: usize = 8;
+        let values = values.into();
+        let table = quote_identifier(table);
+        let mut names = vec!["rowid".to_owned()];
+        let mut placeholders = vec!["?1".to_owned()];
+        for (i, column) in columns.iter().enumerate() {
+            names.push(quote_identifier(column));
+            placeholders.push(format!("?{}", i + 2));
+        }
+        let exists_statement = format!("SELECT 1 FROM {table} WHERE rowid = ?1");
+        let insert_statement = format!(
+            "INSERT INTO {table} ({}) VALUES ({})",
+            names.join(", "),
+            placeholders.join(", "),
+        );
+        self.call_worker(move |conn| {
+            // The rowid is generated here rather than with SQL `random()`,
+            // which is predictable with `ConnectionOptions::random_seed`.
+            let state = RandomState::new();
+            let mut attempt = 0;
+            loop {
+                attempt += 1;
+                let rowid = (state.hash_one(attempt) as i64 & i64::MAX).max(1);
+                let arguments =
+                    std::iter::once(Value::Integer(rowid)).chain(values.iter().cloned());
+                let err = match conn.execute(&insert_statement, params_from_iter(arguments)) {
+                    Ok(_) => return Ok(rowid),
+                    Err(err) => err,
+                };
+                // A conflict on another unique column fails the same way
+                // with any rowid, so only a taken rowid is retried.
+                let taken = err.sqlite_error_code() == Some(ErrorCode::ConstraintViolation)
+                    && conn
+                        .query_row(&exists_statement, [rowid], |_| Ok(()))
+                        .optional()?
+                        .is_some();
+                if !taken || attempt == MAX_ATTEMPTS {
+                    return Err(err.into());
+                }
+            }
+        })
+        .await
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let poisoned = self.tx.as_ref().is_some_and(|v| v.is_poisoned());
+        drop(self.tx.take());
+        let Some(handle) = self.handle.take() else {
+            return;
+        };
+        if poisoned {
+            // Do not wait for the worker that is stuck.
+            return;
+        }
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) if runtime.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                let _ = tokio::task::block_in_place(|| runtime.block_on(handle.join()));
+            }
+            // Waiting is not possible, so the worker finishes in background.
+            _ => handle.abort(),
+        }
+    }
+}
+
+fn named_arguments(arguments: &[(&str, Value)]) -> Arguments {
+    Arguments::Named(
+        arguments
+            .iter()
+            .map(|(name, value)| ((*name).to_owned(), value.clone()))
+            .collect(),
+    )
+}
+
+/// Returns true if the first keyword of the statement is `EXPLAIN`.
+///
+/// Leading whitespace and comments are skipped.
+fn is_explain_statement(statement: &str) -> bool {
+    let mut rest = statement.trim_start();
+    loop {
+        if let Some(comment) = rest.strip_prefix("--") {
+            rest = comment.split_once('\n').map_or("", |v| v.1).trim_start();
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            rest = comment.split_once("*/").map_or("", |v| v.1).trim_start();
+        } else {
+            break;
+        }
+    }
+    let keyword = rest
+        .split(|c: char| !c.is_ascii_alphabetic())
+        .next()
+        .unwrap_or_default();
+    keyword.eq_ignore_ascii_case("EXPLAIN")
+}
+
+/// Returns the compile-time options of the linked SQLite library.
+///
+/// Options are reported without the `SQLITE_` prefix, as in `PRAGMA compile_options`.
+pub fn compile_options() -> Vec<String> {
+    let mut options = Vec::new();
+    for i in 0.. {
+        let option = unsafe { rusqlite::ffi::sqlite3_compileoption_get(i) };
+        if option.is_null() {
+            break;
+        }
+        let option = unsafe { CStr::from_ptr(option) };
+        options.push(option.to_string_lossy().into_owned());
+    }
+    options
+}
+
+/// Escapes the path for use in a URI filename.
+fn uri_path(path: &Path) -> String {
+    let mut uri = String::new();
+    for c in path.to_string_lossy().chars() {
+        match c {
+            '%' => uri.push_str("%25"),
+            '?' => uri.push_str("%3f"),
+            '#' => uri.push_str("%23"),
+            c => uri.push(c),
+        }
+    }
+    uri
+}
+
+/// Quotes an SQL identifier such as a table or column name.
+pub(super) fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
 }
@@ -1,10 +1,16 @@
-use rusqlite::params_from_iter;
+use std::time::Instant;
+
+use rusqlite::TransactionBehavior;
 use tokio::sync::{mpsc, oneshot};
 
 use crate::Error;
 
-use super::query::{ExecuteCommand, QueryCommand, QueryHandle, QueryTask};
-use super::{Status, Value};
+use super::connection::ChangeCounter;
+use super::query::{blocking_query, execute, Arguments, ExecuteCommand, QueryCommand, QueryHandle};
+use super::watchdog::Watchdog;
+use super::Status;
+
+type CallFn = Box<dyn FnOnce(&rusqlite::Connection) + Send>;
 
 enum TransactionCommand {
     Commit {
@@ -13,132 +19,231 @@ enum TransactionCommand {
     Rollback {
         tx: oneshot::Sender<Result<(), Error>>,
     },
+    Restart {
+        tx: oneshot::Sender<Result<(), Error>>,
+    },
     Execute(ExecuteCommand),
     Query(QueryCommand),
-    Shutdown,
+    Batch {
+        statement: String,
+        tx: oneshot::Sender<Result<(), Error>>,
+    },
+    Call(CallFn),
 }
 
-pub(super) struct TransactionHandle(mpsc::Sender<TransactionCommand>);
+pub(super) struct TransactionHandle {
+    tx: mpsc::Sender<TransactionCommand>,
+    /// Statements deferred by dropped savepoints, sent before the next command.
+    deferred: Vec<String>,
+    changes: ChangeCounter,
+    watchdog: Watchdog,
+}
 
 impl TransactionHandle {
     pub async fn commit(&mut self) -> Result<(), Error> {
         let (tx, rx) = oneshot::channel();
-        self.0
-            .send(TransactionCommand::Commit { tx })
-            .await
-            .map_err(|_| Error::InvalidQuery)?;
-        rx.await.unwrap()
+        self.request(TransactionCommand::Commit { tx }, rx).await
     }
 
     pub async fn rollback(&mut self) -> Result<(), Error> {
         let (tx, rx) = oneshot::channel();
-        self.0
-            .send(TransactionCommand::Rollback { tx })
-            .await
-            .map_err(|_| Error::InvalidQuery)?;
-        rx.await.unwrap()
+        self.request(TransactionCommand::Rollback { tx }, rx).await
+    }
+
+    /// Commits the changes and begins a new transaction with the same
+    /// behavior.
+    ///
+    /// If the new transaction cannot be begun, the worker finishes the
+    /// transaction and later commands fail.
+    pub async fn restart(&mut self) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.request(TransactionCommand::Restart { tx }, rx).await
     }
 
     pub async fn execute(
         &mut self,
         statement: String,
-        arguments: Vec<Value>,
+        arguments: Arguments,
     ) -> Result<Status, Error> {
         let (tx, rx) = oneshot::channel();
-        self.0
-            .send(TransactionCommand::Execute(ExecuteCommand {
+        self.request(
+            TransactionCommand::Execute(ExecuteCommand {
                 statement,
                 arguments,
+                enqueued: Instant::now(),
                 tx,
-            }))
+            }),
+            rx,
+        )
+        .await
+    }
+
+    pub async fn execute_batch(&mut self, statement: String) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.request(TransactionCommand::Batch { statement, tx }, rx)
             .await
-            .map_err(|_| Error::InvalidQuery)?;
-        rx.await.unwrap()
     }
 
     pub async fn query(
         &mut self,
         statement: String,
-        arguments: Vec<Value>,
+        arguments: Arguments,
+        capacity: usize,
     ) -> Result<QueryHandle, Error> {
         let (tx, rx) = oneshot::channel();
-        self.0
-            .send(TransactionCommand::Query(QueryCommand {
+        self.request(
+            TransactionCommand::Query(QueryCommand {
                 statement,
                 arguments,
+                capacity,
+                enqueued: Instant::now(),
                 tx,
-            }))
+            }),
+            rx,
+        )
+        .await
+    }
+
+    /// Schedules the statements to run before the next command.
+    ///
+    /// Used where waiting is not possible, for example on drop.
+    pub fn defer_batch(&mut self, statement: String) {
+        self.deferred.push(statement);
+    }
+
+    /// Runs the closure inside the transaction and waits for its result.
+    pub async fn call<F, T>(&mut self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&rusqlite::Connection) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let changes = self.changes.clone();
+        let f = move |conn: &rusqlite::Connection| {
+            let result = f(conn);
+            changes.update(conn);
+            let _ = tx.send(result);
+        };
+        self.request(TransactionCommand::Call(Box::new(f)), rx)
             .await
-            .map_err(|_| Error::InvalidQuery)?;
-        rx.await.unwrap()
     }
-}
 
-impl Drop for TransactionHandle {
-    fn drop(&mut self) {
-        let _ = tokio::task::block_in_place(|| {
-            tokio::runtime::Handle::current().block_on(self.0.send(TransactionCommand::Shutdown))
-        });
+    /// Sends the command to the worker and waits for the result.
+    async fn request<T>(
+        &mut self,
+        cmd: TransactionCommand,
+        rx: oneshot::Receiver<Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let tx = &self.tx;
+        let deferred = std::mem::take(&mut self.deferred);
+        self.watchdog
+            .watch(async move {
+                for statement in deferred {
+                    let (batch_tx, batch_rx) = oneshot::channel();
+                    tx.send(TransactionCommand::Batch {
+                        statement,
+                        tx: batch_tx,
+                    })
+                    .await
+                    .map_err(|_| Error::WorkerClosed)?;
+                    batch_rx.await.map_err(|_| Error::WorkerClosed)??;
+                }
+                tx.send(cmd).await.map_err(|_| Error::WorkerClosed)?;
+                rx.await.map_err(|_| Error::WorkerClosed)?
+            })
+            .await
     }
 }
 
 pub(super) struct TransactionTask<'a> {
     conn: &'a mut rusqlite::Connection,
+    behavior: TransactionBehavior,
+    changes: ChangeCounter,
+    watchdog: Watchdog,
 }
 
 impl<'a> TransactionTask<'a> {
-    pub fn new(conn: &'a mut rusqlite::Connection) -> Self {
-        Self { conn }
+    pub fn new(
+        conn: &'a mut rusqlite::Connection,
+        behavior: TransactionBehavior,
+        changes: ChangeCounter,
+        watchdog: Watchdog,
+    ) -> Self {
+        Self {
+            conn,
+            behavior,
+            changes,
+            watchdog,
+        }
     }
 
     pub fn blocking_run(self, handle_rx: oneshot::Sender<Result<TransactionHandle, Error>>) {
-        let transaction = match self
-            .conn
-            .transaction_with_behavior(rusqlite::TransactionBehavior::Deferred)
-        {
+        if !self.conn.is_autocommit() {
+            // Nested transactions require savepoints.
+            let _ = handle_rx.send(Err(Error::TransactionAlreadyOpen));
+            return;
+        }
+        let transaction = match self.conn.transaction_with_behavior(self.behavior) {
             Ok(conn) => conn,
             Err(err) => {
-                let _ = handle_rx.send(Err(err));
+                let _ = handle_rx.send(Err(err.into()));
                 return;
             }
         };
         let (tx, mut rx) = mpsc::channel(1);
-        if let Err(_) = handle_rx.send(Ok(TransactionHandle(tx))) {
+        let handle = TransactionHandle {
+            tx,
+            deferred: Vec::new(),
+            changes: self.changes.clone(),
+            watchdog: self.watchdog.clone(),
+        };
+        if handle_rx.send(Ok(handle)).is_err() {
             // Drop transaction if nobody listens result.
             return;
         }
         while let Some(cmd) = rx.blocking_recv() {
             match cmd {
                 TransactionCommand::Commit { tx } => {
-                    let _ = tx.send(transaction.commit());
+                    let _ = tx.send(transaction.commit().map_err(Error::from));
                     return;
                 }
                 TransactionCommand::Rollback { tx } => {
-                    let _ = tx.send(transaction.rollback());
+                    let _ = tx.send(transaction.rollback().map_err(Error::from));
                     return;
                 }
+                TransactionCommand::Restart { tx } => {
+                    let begin = match self.behavior {
+                        TransactionBehavior::Immediate => "BEGIN IMMEDIATE",
+                        TransactionBehavior::Exclusive => "BEGIN EXCLUSIVE",
+                        _ => "BEGIN DEFERRED",
+                    };
+                    // The transaction stays open if the commit fails.
+                    if let Err(err) = transaction.execute_batch("COMMIT") {
+                        let _ = tx.send(Err(err.into()));
+                        continue;
+                    }
+                    // The changes are committed, so the transaction is
+                    // finished if the new one cannot be started.
+                    if let Err(err) = transaction.execute_batch(begin) {
+                        let _ = tx.send(Err(err.into()));
+                        return;
+                    }
+                    let _ = tx.send(Ok(()));
+                }
                 TransactionCommand::Execute(cmd) => {
-                    let _ = cmd.tx.send(
-                        transaction
-                            .execute(&cmd.statement, params_from_iter(cmd.arguments.into_iter()))
-                            .map(|rows_affected| Status {
-                                rows_affected,
-                                last_insert_id: Some(transaction.last_insert_rowid()),
-                            }),
-                    );
+                    let status = execute(&transaction, &cmd);
+                    self.changes.update(&transaction);
+                    let _ = cmd.tx.send(status);
+                }
+                TransactionCommand::Batch { statement, tx } => {
+                    let result = transaction.execute_batch(&statement).map_err(Error::from);
+                    self.changes.update(&transaction);
+                    let _ = tx.send(result);
                 }
                 TransactionCommand::Query(cmd) => {
-                    let stmt = match transaction.prepare(&cmd.statement) {
-                        Ok(stmt) => stmt,
-                        Err(err) => {
-                            let _ = cmd.tx.send(Err(err));
-                            continue;
-                        }
-                    };
-                    let task = QueryTask::new(stmt, cmd.arguments);
-                    task.blocking_run(cmd.tx);
+                    blocking_query(&transaction, cmd, &self.changes, self.watchdog.clone());
                 }
-                TransactionCommand::Shutdown => return,
+                TransactionCommand::Call(f) => f(&transaction),
             }
         }
     }
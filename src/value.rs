@@ -0,0 +1,303 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+#[cfg(feature = "bytes")]
+use bytes::Bytes;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ValueRef};
+
+use super::{Error, Row, Value};
+
+/// A type that can be converted from an SQLite value.
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> FromSqlResult<Self>;
+}
+
+macro_rules! from_sql_impl {
+    ($($t:ty),*) => {
+        $(
+            impl FromValue for $t {
+                fn from_value(value: &Value) -> FromSqlResult<Self> {
+                    FromSql::column_result(ValueRef::from(value))
+                }
+            }
+        )*
+    };
+}
+
+from_sql_impl!(i8, i16, i32, i64, isize);
+from_sql_impl!(u8, u16, u32, u64, usize);
+from_sql_impl!(f32, f64, bool, String, Vec<u8>);
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> FromSqlResult<Self> {
+        Ok(value.clone())
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> FromSqlResult<Self> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+/// A type that can be converted into an SQLite value.
+///
+/// Unlike `Into<Value>`, it is implemented for borrowed strings and blobs
+/// and for all primitive integers. Booleans are stored as `0` and `1`.
+/// Unsigned integers above `i64::MAX` wrap around into negative values, so
+/// they should be read back as `i64` and cast with `as u64`.
+///
+/// See the [`values!`](crate::values) macro.
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+macro_rules! into_value_impl {
+    ($($t:ty),*) => {
+        $(
+            impl IntoValue for $t {
+                fn into_value(self) -> Value {
+                    self.into()
+                }
+            }
+        )*
+    };
+}
+
+into_value_impl!(i8, i16, i32, i64, isize, u8, u16, u32);
+into_value_impl!(f32, f64, bool, String, Vec<u8>, Value);
+
+impl IntoValue for u64 {
+    fn into_value(self) -> Value {
+        Value::Integer(self as i64)
+    }
+}
+
+impl IntoValue for usize {
+    fn into_value(self) -> Value {
+        Value::Integer(self as i64)
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::Text(self.to_owned())
+    }
+}
+
+impl IntoValue for &[u8] {
+    fn into_value(self) -> Value {
+        Value::Blob(self.to_vec())
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        self.map_or(Value::Null, T::into_value)
+    }
+}
+
+/// Creates a vector of values from expressions of [`IntoValue`] types, for
+/// example `values![true, 42u32, "x"]`.
+#[macro_export]
+macro_rules! values {
+    ($($value:expr),* $(,)?) => {
+        ::std::vec![$($crate::IntoValue::into_value($value)),*]
+    };
+}
+
+/// A storage format of IP addresses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpFormat {
+    /// The textual representation, such as `127.0.0.1` or `::1`.
+    Text,
+    /// A blob of 4 bytes for IPv4 and 16 bytes for IPv6 addresses.
+    Blob,
+}
+
+/// Converts the IP address into a value of the format.
+pub fn ip_value<A: Into<IpAddr>>(addr: A, format: IpFormat) -> Value {
+    let addr = addr.into();
+    match (format, addr) {
+        (IpFormat::Text, addr) => Value::Text(addr.to_string()),
+        (IpFormat::Blob, IpAddr::V4(addr)) => Value::Blob(addr.octets().to_vec()),
+        (IpFormat::Blob, IpAddr::V6(addr)) => Value::Blob(addr.octets().to_vec()),
+    }
+}
+
+/// Reads the IP address stored in any of the [`IpFormat`] formats.
+///
+/// Blobs are distinguished by their length.
+impl FromValue for IpAddr {
+    fn from_value(value: &Value) -> FromSqlResult<Self> {
+        match value {
+            Value::Text(v) => v.parse().map_err(|err| FromSqlError::Other(Box::new(err))),
+            Value::Blob(v) => match v.len() {
+                4 => Ok(IpAddr::from(<[u8; 4]>::try_from(v.as_slice()).unwrap())),
+                16 => Ok(IpAddr::from(<[u8; 16]>::try_from(v.as_slice()).unwrap())),
+                len => Err(FromSqlError::InvalidBlobSize {
+                    expected_size: 16,
+                    blob_size: len,
+                }),
+            },
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl FromValue for Ipv4Addr {
+    fn from_value(value: &Value) -> FromSqlResult<Self> {
+        match IpAddr::from_value(value)? {
+            IpAddr::V4(addr) => Ok(addr),
+            IpAddr::V6(_) => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl FromValue for Ipv6Addr {
+    fn from_value(value: &Value) -> FromSqlResult<Self> {
+        match IpAddr::from_value(value)? {
+            IpAddr::V4(_) => Err(FromSqlError::InvalidType),
+            IpAddr::V6(addr) => Ok(addr),
+        }
+    }
+}
+
+/// A unit of durations stored as integers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DurationUnit {
+    Seconds,
+    Milliseconds,
+    Microseconds,
+    Nanoseconds,
+}
+
+impl DurationUnit {
+    fn nanos(&self) -> u128 {
+        match self {
+            DurationUnit::Seconds => 1_000_000_000,
+            DurationUnit::Milliseconds => 1_000_000,
+            DurationUnit::Microseconds => 1_000,
+            DurationUnit::Nanoseconds => 1,
+        }
+    }
+}
+
+/// Converts the duration into an integer number of the units.
+///
+/// The remainder smaller than the unit is truncated. Returns an error if the
+/// number does not fit into `i64`, which for nanoseconds happens above
+/// roughly 292 years.
+pub fn duration_value(duration: Duration, unit: DurationUnit) -> Result<Value, Error> {
+    let value = i64::try_from(duration.as_nanos() / unit.nanos())
+        .map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err)))?;
+    Ok(Value::Integer(value))
+}
+
+/// Converts the non-negative integer number of the units into a duration.
+pub(super) fn duration_from_value(value: &Value, unit: DurationUnit) -> FromSqlResult<Duration> {
+    let value = i64::from_value(value)?;
+    let count = u64::try_from(value).map_err(|_| FromSqlError::OutOfRange(value))?;
+    Ok(match unit {
+        DurationUnit::Seconds => Duration::from_secs(count),
+        DurationUnit::Milliseconds => Duration::from_millis(count),
+        DurationUnit::Microseconds => Duration::from_micros(count),
+        DurationUnit::Nanoseconds => Duration::from_nanos(count),
+    })
+}
+
+/// A type that can be converted from a resulting query row.
+pub trait FromRow: Sized {
+    fn from_row(row: &Row) -> Result<Self, Error>;
+}
+
+impl FromRow for Row {
+    fn from_row(row: &Row) -> Result<Self, Error> {
+        Ok(row.clone())
+    }
+}
+
+macro_rules! from_row_tuple_impl {
+    ($($t:ident: $i:tt),*) => {
+        impl<$($t: FromValue),*> FromRow for ($($t,)*) {
+            fn from_row(row: &Row) -> Result<Self, Error> {
+                Ok(($(row.get::<$t>($i)?,)*))
+            }
+        }
+    };
+}
+
+from_row_tuple_impl!(A: 0);
+from_row_tuple_impl!(A: 0, B: 1);
+from_row_tuple_impl!(A: 0, B: 1, C: 2);
+from_row_tuple_impl!(A: 0, B: 1, C: 2, D: 3);
+from_row_tuple_impl!(A: 0, B: 1, C: 2, D: 3, E: 4);
+from_row_tuple_impl!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5);
+from_row_tuple_impl!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6);
+from_row_tuple_impl!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7);
+from_row_tuple_impl!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8);
+from_row_tuple_impl!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8, J: 9);
+from_row_tuple_impl!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8, J: 9, K: 10);
+from_row_tuple_impl!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8, J: 9, K: 10, L: 11);
+
+/// Returns the approximate size of the value in memory.
+pub(super) fn value_size(value: &Value) -> usize {
+    match value {
+        Value::Null => 0,
+        Value::Integer(_) | Value::Real(_) => 8,
+        Value::Text(v) => v.len(),
+        Value::Blob(v) => v.len(),
+    }
+}
+
+/// Converts the value into a JSON value.
+///
+/// Blobs are converted into base64 strings and non-finite reals into `null`.
+#[cfg(feature = "serde_json")]
+pub(super) fn json_value(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Integer(v) => (*v).into(),
+        Value::Real(v) => {
+            serde_json::Number::from_f64(*v).map_or(serde_json::Value::Null, Into::into)
+        }
+        Value::Text(v) => v.as_str().into(),
+        Value::Blob(v) => base64(v).into(),
+    }
+}
+
+/// Encodes the bytes with the standard base64 alphabet and padding.
+#[cfg(feature = "serde_json")]
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, b[0], b[1], b[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// Converts the shared buffer into a blob value.
+///
+/// Values are sent to the connection worker by ownership, so a buffer shared
+/// with other [`Bytes`] handles is copied once. A uniquely owned buffer is
+/// converted without copying.
+#[cfg(feature = "bytes")]
+pub fn blob_from_bytes(bytes: Bytes) -> Value {
+    Value::Blob(bytes.into())
+}
@@ -0,0 +1,46 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::Error;
+
+/// Poisons the connection if the worker does not respond in time.
+#[derive(Clone, Default)]
+pub(super) struct Watchdog {
+    timeout: Option<Duration>,
+    poisoned: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            timeout,
+            poisoned: Default::default(),
+        }
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+
+    /// Awaits the future, poisoning the connection if the timeout elapses.
+    pub async fn watch<F, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: Future<Output = Result<T, Error>>,
+    {
+        if self.is_poisoned() {
+            return Err(Error::Poisoned);
+        }
+        let Some(timeout) = self.timeout else {
+            return f.await;
+        };
+        match tokio::time::timeout(timeout, f).await {
+            Ok(result) => result,
+            Err(_) => {
+                self.poisoned.store(true, Ordering::Relaxed);
+                Err(Error::Poisoned)
+            }
+        }
+    }
+}
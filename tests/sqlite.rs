@@ -1,4 +1,26 @@
-use tokio_sqlite::{Connection, Value};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio_sqlite::{
+    duration_value, ip_value, Connection, ConnectionOptions, CsvOptions, DurationUnit, Error,
+    FromRow, IpFormat, JournalMode, Limit, OpenFlags, Pool, RetryPolicy, Row, TransactionBehavior,
+    Value,
+};
+use tokio_stream::StreamExt;
+
+fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "tokio-sqlite-{}-{}.sqlite",
+        std::process::id(),
+        name
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
 
 #[tokio::test(flavor = "multi_thread")]
 async fn test_sqlite() {
@@ -107,3 +129,3198 @@ async fn test_sqlite() {
         .unwrap();
     assert!(row.is_some());
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_insert_with_random_rowid() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute(
+        r#"CREATE TABLE test_tbl (a INTEGER PRIMARY KEY, b TEXT NOT NULL)"#,
+        [],
+    )
+    .await
+    .unwrap();
+    let mut ids = Vec::new();
+    for i in 0..10 {
+        let id = conn
+            .insert_with_random_rowid("test_tbl", &["b"], [format!("test{i}").into()])
+            .await
+            .unwrap();
+        assert!(id > 0);
+        ids.push(id);
+    }
+    assert!(ids.windows(2).any(|w| w[1] != w[0] + 1));
+    let mut unique = ids.clone();
+    unique.sort();
+    unique.dedup();
+    assert_eq!(unique.len(), ids.len());
+    let row = conn
+        .query_row("SELECT b FROM test_tbl WHERE a = $1", [ids[3].into()])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.values(), vec![Value::Text("test3".to_owned())]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_insert_with_random_rowid_conflict() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (k TEXT PRIMARY KEY, v INTEGER)", [])
+        .await
+        .unwrap();
+    conn.insert_with_random_rowid("test_tbl", &["k", "v"], ["a".to_owned().into(), 1.into()])
+        .await
+        .unwrap();
+    // The duplicate key conflicts with any rowid, so it is not retried.
+    let err = conn
+        .insert_with_random_rowid("test_tbl", &["k", "v"], ["a".to_owned().into(), 2.into()])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Sqlite(err) if err.sqlite_error_code() == Some(rusqlite::ErrorCode::ConstraintViolation)
+    ));
+    let row = conn
+        .query_row("SELECT COUNT(*) FROM test_tbl", [])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.values(), vec![Value::Integer(1)]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_insert_with_random_rowid_seeded() {
+    let mut ids = Vec::new();
+    for _ in 0..2 {
+        let options = ConnectionOptions::new().random_seed(42);
+        let mut conn = Connection::open_with_options(":memory:", options)
+            .await
+            .unwrap();
+        conn.execute("CREATE TABLE test_tbl (a INTEGER PRIMARY KEY, b TEXT)", [])
+            .await
+            .unwrap();
+        let id = conn
+            .insert_with_random_rowid("test_tbl", &["b"], ["test".to_owned().into()])
+            .await
+            .unwrap();
+        ids.push(id);
+    }
+    // The rowid does not depend on the seed of SQL random functions.
+    assert_ne!(ids[0], ids[1]);
+}
+
+#[test]
+fn test_compile_options() {
+    let options = tokio_sqlite::compile_options();
+    assert!(!options.is_empty());
+    assert!(options.iter().all(|v| !v.starts_with("SQLITE_")));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_json_extract() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    if !conn.has_json_support().await {
+        return;
+    }
+    conn.execute(
+        r#"CREATE TABLE test_tbl (a INTEGER PRIMARY KEY, b TEXT)"#,
+        [],
+    )
+    .await
+    .unwrap();
+    conn.execute(
+        r#"INSERT INTO test_tbl (b) VALUES ($1), ($2)"#,
+        [
+            r#"{"author": {"name": "test1"}}"#.to_owned().into(),
+            r#"{"author": {}}"#.to_owned().into(),
+        ],
+    )
+    .await
+    .unwrap();
+    let values = conn
+        .query_json_extract("test_tbl", "b", "$.author.name")
+        .await
+        .unwrap();
+    assert_eq!(values, vec![Value::Text("test1".to_owned()), Value::Null]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_value_too_large() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute(
+        r#"CREATE TABLE test_tbl (a INTEGER PRIMARY KEY, b BLOB)"#,
+        [],
+    )
+    .await
+    .unwrap();
+    conn.set_limit(Limit::SQLITE_LIMIT_LENGTH, 1024)
+        .await
+        .unwrap();
+    let err = conn
+        .execute(
+            r#"INSERT INTO test_tbl (b) VALUES ($1)"#,
+            [Value::Blob(vec![0; 2048])],
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        Error::ValueTooLarge {
+            size: 2048,
+            limit: 1024
+        }
+    ));
+    conn.execute(
+        r#"INSERT INTO test_tbl (b) VALUES ($1)"#,
+        [Value::Blob(vec![0; 512])],
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_with_savepoint() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute(
+        r#"CREATE TABLE test_tbl (a INTEGER PRIMARY KEY, b TEXT NOT NULL)"#,
+        [],
+    )
+    .await
+    .unwrap();
+    let result = conn
+        .with_savepoint(|conn| {
+            Box::pin(async move {
+                conn.execute(r#"INSERT INTO test_tbl (b) VALUES ("test1")"#, [])
+                    .await?;
+                conn.execute(r#"INSERT INTO test_tbl (b) VALUES (NULL)"#, [])
+                    .await?;
+                Ok(())
+            })
+        })
+        .await;
+    assert!(result.is_err());
+    let row = conn
+        .query_row("SELECT COUNT(*) FROM test_tbl", [])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.values(), vec![Value::Integer(0)]);
+    let id = conn
+        .with_savepoint(|conn| {
+            Box::pin(async move {
+                let status = conn
+                    .execute(r#"INSERT INTO test_tbl (b) VALUES ("test2")"#, [])
+                    .await?;
+                Ok(status.last_insert_id())
+            })
+        })
+        .await
+        .unwrap();
+    assert_eq!(id, Some(1));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_query_map_kv() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute(
+        r#"CREATE TABLE test_tbl (a TEXT PRIMARY KEY, b INTEGER NOT NULL)"#,
+        [],
+    )
+    .await
+    .unwrap();
+    conn.execute(
+        r#"INSERT INTO test_tbl (a, b) VALUES ("test1", 1), ("test2", 2)"#,
+        [],
+    )
+    .await
+    .unwrap();
+    let map: HashMap<String, i64> = conn
+        .query_map_kv("SELECT a, b FROM test_tbl", [])
+        .await
+        .unwrap();
+    assert_eq!(map.len(), 2);
+    assert_eq!(map["test1"], 1);
+    assert_eq!(map["test2"], 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_query_transform() {
+    #[derive(Debug, PartialEq)]
+    struct Item {
+        name: String,
+        total: i64,
+    }
+
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute(
+        r#"CREATE TABLE test_tbl (a TEXT NOT NULL, b INTEGER NOT NULL, c INTEGER NOT NULL)"#,
+        [],
+    )
+    .await
+    .unwrap();
+    conn.execute(
+        r#"INSERT INTO test_tbl (a, b, c) VALUES ("test1", 2, 3), ("test2", 4, 5)"#,
+        [],
+    )
+    .await
+    .unwrap();
+    let items = conn
+        .query_transform("SELECT a, b, c FROM test_tbl ORDER BY a", [], |row| {
+            Ok(Item {
+                name: row.get(0)?,
+                total: row.get::<_, i64>(1)? * row.get::<_, i64>(2)?,
+            })
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        items,
+        vec![
+            Item {
+                name: "test1".to_owned(),
+                total: 6,
+            },
+            Item {
+                name: "test2".to_owned(),
+                total: 20,
+            },
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_row_get_by_name() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute(
+        r#"CREATE TABLE test_tbl (a INTEGER PRIMARY KEY, b TEXT NOT NULL)"#,
+        [],
+    )
+    .await
+    .unwrap();
+    conn.execute(r#"INSERT INTO test_tbl (b) VALUES ("test1")"#, [])
+        .await
+        .unwrap();
+    let row = conn
+        .query_row("SELECT a, b FROM test_tbl", [])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.columns(), vec!["a", "b"]);
+    assert_eq!(row.get_by_name::<String>("b").unwrap(), "test1");
+    assert_eq!(row.get::<i64>(0).unwrap(), 1);
+    assert!(row.get_by_name::<String>("c").is_err());
+    assert!(row.get_by_name::<i64>("b").is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_row_get_or() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    let row = conn
+        .query_row("SELECT NULL, 5, 'text'", [])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.get_or::<i64>(0, 10), 10);
+    assert_eq!(row.get_or::<i64>(1, 10), 5);
+    assert_eq!(row.get_or::<i64>(2, 10), 10);
+    assert_eq!(row.get_or::<i64>(3, 10), 10);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_nulls_order() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute(
+        r#"CREATE TABLE test_tbl (a INTEGER PRIMARY KEY, b INTEGER)"#,
+        [],
+    )
+    .await
+    .unwrap();
+    conn.execute(r#"INSERT INTO test_tbl (b) VALUES (2), (NULL), (1)"#, [])
+        .await
+        .unwrap();
+    let statement = "SELECT a FROM test_tbl ORDER BY b DESC NULLS LAST";
+    for statement in [
+        tokio_sqlite::nulls_order_compat(statement).into_owned(),
+        tokio_sqlite::rewrite_nulls_order(statement),
+    ] {
+        let mut rows = conn.query(statement, []).await.unwrap();
+        let mut ids = Vec::new();
+        while let Some(row) = rows.next().await {
+            ids.push(row.unwrap().get::<i64>(0).unwrap());
+        }
+        assert_eq!(ids, vec![1, 3, 2]);
+    }
+    let statement = "SELECT a FROM test_tbl ORDER BY b NULLS FIRST";
+    let mut rows = conn
+        .query(tokio_sqlite::rewrite_nulls_order(statement), [])
+        .await
+        .unwrap();
+    let mut ids = Vec::new();
+    while let Some(row) = rows.next().await {
+        ids.push(row.unwrap().get::<i64>(0).unwrap());
+    }
+    assert_eq!(ids, vec![2, 3, 1]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_named_arguments() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute(
+        r#"CREATE TABLE test_tbl (a INTEGER PRIMARY KEY, b TEXT NOT NULL)"#,
+        [],
+    )
+    .await
+    .unwrap();
+    conn.execute_named(
+        r#"INSERT INTO test_tbl (a, b) VALUES (:a, :b)"#,
+        &[(":b", "test1".to_owned().into()), (":a", 5.into())],
+    )
+    .await
+    .unwrap();
+    let mut rows = conn
+        .query_named(
+            "SELECT a, b FROM test_tbl WHERE a = $a",
+            &[("$a", 5.into())],
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        rows.next().await.unwrap().unwrap().values(),
+        vec![Value::Integer(5), Value::Text("test1".to_owned())]
+    );
+    drop(rows);
+    let mut tx = conn.transaction().await.unwrap();
+    tx.execute_named(
+        r#"INSERT INTO test_tbl (b) VALUES (:b)"#,
+        &[(":b", "test2".to_owned().into())],
+    )
+    .await
+    .unwrap();
+    assert!(tx
+        .execute_named(
+            r#"INSERT INTO test_tbl (b) VALUES (:b)"#,
+            &[(":c", "test3".to_owned().into())],
+        )
+        .await
+        .is_err());
+    tx.commit().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_execute_batch() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch(
+        r#"
+        CREATE TABLE test_tbl1 (a INTEGER PRIMARY KEY, b TEXT NOT NULL);
+        CREATE TABLE test_tbl2 (a INTEGER PRIMARY KEY, b INTEGER NOT NULL);
+        INSERT INTO test_tbl1 (b) VALUES ('test1');
+        INSERT INTO test_tbl2 (b) VALUES (1), (2);
+        "#,
+    )
+    .await
+    .unwrap();
+    let row = conn
+        .query_row(
+            "SELECT (SELECT COUNT(*) FROM test_tbl1), (SELECT COUNT(*) FROM test_tbl2)",
+            [],
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.values(), vec![Value::Integer(1), Value::Integer(2)]);
+    let mut tx = conn.transaction().await.unwrap();
+    tx.execute_batch("INSERT INTO test_tbl1 (b) VALUES ('test2'); DELETE FROM test_tbl2;")
+        .await
+        .unwrap();
+    tx.rollback().await.unwrap();
+    let row = conn
+        .query_row("SELECT COUNT(*) FROM test_tbl2", [])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.values(), vec![Value::Integer(2)]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_dump() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch(
+        r#"
+        CREATE TABLE test_tbl (
+            a INTEGER PRIMARY KEY, b TEXT, c BLOB, d REAL,
+            e INTEGER GENERATED ALWAYS AS (a * 2)
+        );
+        CREATE INDEX test_idx ON test_tbl (b);
+        INSERT INTO test_tbl (b, c, d) VALUES ('it''s; test', X'00FF', 1.5);
+        INSERT INTO test_tbl (b, c, d) VALUES (NULL, NULL, NULL);
+        "#,
+    )
+    .await
+    .unwrap();
+    let mut statements = Vec::new();
+    let mut dump = conn.dump().await.unwrap();
+    while let Some(statement) = dump.next().await {
+        statements.push(statement.unwrap());
+    }
+    drop(dump);
+    let mut copy = Connection::open(":memory:").await.unwrap();
+    copy.execute_batch(&statements.join("\n")).await.unwrap();
+    let statement = "SELECT a, b, c, d, e FROM test_tbl ORDER BY a";
+    let mut rows = conn.query(statement, []).await.unwrap();
+    let mut copy_rows = copy.query(statement, []).await.unwrap();
+    for _ in 0..2 {
+        assert_eq!(
+            rows.next().await.unwrap().unwrap().values(),
+            copy_rows.next().await.unwrap().unwrap().values(),
+        );
+    }
+    assert!(copy_rows.next().await.is_none());
+    drop(copy_rows);
+    let row = copy
+        .query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE name = 'test_idx'",
+            [],
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.values(), vec![Value::Integer(1)]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_restore_dump() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch(
+        r#"
+        CREATE TABLE test_tbl (a INTEGER PRIMARY KEY AUTOINCREMENT, b TEXT);
+        CREATE TABLE test_log (a INTEGER);
+        CREATE TRIGGER test_trg AFTER INSERT ON test_tbl BEGIN
+            INSERT INTO test_log (a) VALUES (NEW.a);
+        END;
+        INSERT INTO test_tbl (b) VALUES ('multi
+line; text'), ('test2');
+        "#,
+    )
+    .await
+    .unwrap();
+    let mut statements = Vec::new();
+    let mut dump = conn.dump().await.unwrap();
+    while let Some(statement) = dump.next().await {
+        statements.push(statement.unwrap());
+    }
+    drop(dump);
+    let dump = statements.join("\n");
+    let mut copy = Connection::open(":memory:").await.unwrap();
+    copy.restore_dump(dump.as_bytes()).await.unwrap();
+    let statement = "SELECT a, b FROM test_tbl ORDER BY a";
+    let mut rows = conn.query(statement, []).await.unwrap();
+    let mut copy_rows = copy.query(statement, []).await.unwrap();
+    for _ in 0..2 {
+        assert_eq!(
+            rows.next().await.unwrap().unwrap().values(),
+            copy_rows.next().await.unwrap().unwrap().values(),
+        );
+    }
+    assert!(copy_rows.next().await.is_none());
+    drop(copy_rows);
+    copy.execute("INSERT INTO test_tbl (b) VALUES ('test3')", [])
+        .await
+        .unwrap();
+    let row = copy
+        .query_row(
+            "SELECT MAX(a), (SELECT COUNT(*) FROM test_log) FROM test_tbl",
+            [],
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.values(), vec![Value::Integer(3), Value::Integer(3)]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_open_with_flags() {
+    let path = temp_path("open_with_flags");
+    assert!(
+        Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_WRITE)
+            .await
+            .is_err()
+    );
+    let mut conn = Connection::open(&path).await.unwrap();
+    conn.execute(
+        r#"CREATE TABLE test_tbl (a INTEGER PRIMARY KEY, b TEXT NOT NULL)"#,
+        [],
+    )
+    .await
+    .unwrap();
+    drop(conn);
+    let mut conn = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .await
+        .unwrap();
+    assert!(conn
+        .execute(r#"INSERT INTO test_tbl (b) VALUES ("test1")"#, [])
+        .await
+        .is_err());
+    let row = conn
+        .query_row("SELECT COUNT(*) FROM test_tbl", [])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.values(), vec![Value::Integer(0)]);
+    drop(conn);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_pool_named_statements() {
+    let path = temp_path("pool_named_statements");
+    let options = ConnectionOptions::new().journal_mode(JournalMode::Wal);
+    let pool = Pool::new(&path, options, 2);
+    pool.register_statement("insert", "INSERT INTO test_tbl VALUES (?1)");
+    pool.register_statement("count", "SELECT COUNT(*), SUM(a) FROM test_tbl");
+    let mut first = pool.acquire().await.unwrap();
+    first
+        .execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    let mut second = pool.acquire().await.unwrap();
+    // Each connection prepares the statement on its first use and reuses it.
+    for i in 1..=4 {
+        let conn = if i % 2 == 0 { &mut first } else { &mut second };
+        let status = conn
+            .execute_statement("insert", [Value::Integer(i)])
+            .await
+            .unwrap();
+        assert_eq!(status.rows_affected(), 1);
+    }
+    for conn in [&mut first, &mut second] {
+        let rows = conn
+            .query_statement("count", [], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?))
+            })
+            .await
+            .unwrap();
+        assert_eq!(rows, vec![(4, 10)]);
+    }
+    let err = first.execute_statement("missing", []).await.unwrap_err();
+    assert!(matches!(err, Error::UnknownStatement { name } if name == "missing"));
+    drop((first, second));
+    drop(pool);
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_pool_total_changes() {
+    let path = temp_path("pool_total_changes");
+    let options = ConnectionOptions::new().journal_mode(JournalMode::Wal);
+    let pool = Pool::new(&path, options, 2);
+    let mut first = pool.acquire().await.unwrap();
+    first
+        .execute_batch("CREATE TABLE test_tbl (a INTEGER); INSERT INTO test_tbl VALUES (1), (2)")
+        .await
+        .unwrap();
+    let mut second = pool.acquire().await.unwrap();
+    let mut tx = second.transaction().await.unwrap();
+    tx.execute("INSERT INTO test_tbl VALUES (3), (4), (5)", &[])
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+    // Changes are counted once the connections are released.
+    assert_eq!(pool.total_changes(), 0);
+    drop(first);
+    assert_eq!(pool.total_changes(), 2);
+    drop(second);
+    assert_eq!(pool.total_changes(), 5);
+    // Connections taken again only add their new changes.
+    let mut conn = pool.acquire().await.unwrap();
+    let mut rows = conn
+        .query("DELETE FROM test_tbl WHERE a > 3 RETURNING a", [])
+        .await
+        .unwrap();
+    let mut count = 0;
+    while let Some(row) = rows.next().await {
+        row.unwrap();
+        count += 1;
+    }
+    assert_eq!(count, 2);
+    drop(rows);
+    drop(conn);
+    assert_eq!(pool.total_changes(), 7);
+    drop(pool);
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_watchdog_timeout() {
+    let options = ConnectionOptions::new().watchdog_timeout(Duration::from_millis(50));
+    let mut conn = Connection::open_with_options(":memory:", options)
+        .await
+        .unwrap();
+    // The function blocks the worker until the sender is dropped.
+    let (release, blocked) = std::sync::mpsc::channel::<()>();
+    conn.create_scalar_function("test_block", 0, move |_| {
+        let _ = blocked.recv();
+        Ok(Value::Null)
+    })
+    .await
+    .unwrap();
+    let err = conn.query_row("SELECT test_block()", []).await.unwrap_err();
+    assert!(matches!(err, Error::Poisoned));
+    let err = conn.execute("SELECT 1", []).await.unwrap_err();
+    assert!(matches!(err, Error::Poisoned));
+    drop(release);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_busy_timeout() {
+    let path = temp_path("busy_timeout");
+    let mut conn = Connection::open(&path).await.unwrap();
+    conn.execute(
+        r#"CREATE TABLE test_tbl (a INTEGER PRIMARY KEY, b TEXT NOT NULL)"#,
+        [],
+    )
+    .await
+    .unwrap();
+    let options = ConnectionOptions::new().busy_timeout(Duration::from_secs(5));
+    let mut other = Connection::open_with_options(&path, options).await.unwrap();
+    let (locked_tx, locked_rx) = tokio::sync::oneshot::channel();
+    let task = tokio::spawn(async move {
+        let mut tx = conn.transaction().await.unwrap();
+        tx.execute(r#"INSERT INTO test_tbl (b) VALUES ("test1")"#, &[])
+            .await
+            .unwrap();
+        locked_tx.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        tx.commit().await.unwrap();
+    });
+    locked_rx.await.unwrap();
+    other
+        .execute(r#"INSERT INTO test_tbl (b) VALUES ("test2")"#, [])
+        .await
+        .unwrap();
+    task.await.unwrap();
+    other.set_busy_timeout(Duration::ZERO).await.unwrap();
+    let row = other
+        .query_row("SELECT COUNT(*) FROM test_tbl", [])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.values(), vec![Value::Integer(2)]);
+    drop(other);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "bytes")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_blob_from_bytes() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute(
+        r#"CREATE TABLE test_tbl (a INTEGER PRIMARY KEY, b BLOB)"#,
+        [],
+    )
+    .await
+    .unwrap();
+    let blob = bytes::Bytes::from(vec![7u8; 1 << 20]);
+    for _ in 0..3 {
+        conn.execute(
+            r#"INSERT INTO test_tbl (b) VALUES ($1)"#,
+            [tokio_sqlite::blob_from_bytes(blob.clone())],
+        )
+        .await
+        .unwrap();
+    }
+    let row = conn
+        .query_row(
+            "SELECT COUNT(*), SUM(length(b)) FROM test_tbl WHERE b = $1",
+            [tokio_sqlite::blob_from_bytes(blob)],
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        row.values(),
+        vec![Value::Integer(3), Value::Integer(3 << 20)]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_connection_builder_journal_mode() {
+    // In-memory databases do not support write-ahead logging.
+    let result = Connection::builder()
+        .journal_mode(JournalMode::Wal)
+        .open()
+        .await;
+    assert!(matches!(
+        result,
+        Err(Error::JournalMode {
+            requested: JournalMode::Wal,
+            ref applied,
+        }) if applied == "memory"
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_connection_builder() {
+    let path = temp_path("connection_builder");
+    let mut conn = Connection::builder()
+        .path(&path)
+        .journal_mode(JournalMode::Wal)
+        .foreign_keys(true)
+        .busy_timeout(Duration::from_secs(1))
+        .open()
+        .await
+        .unwrap();
+    conn.execute_batch(
+        r#"
+        CREATE TABLE test_parent (a INTEGER PRIMARY KEY);
+        CREATE TABLE test_child (a INTEGER PRIMARY KEY, b INTEGER REFERENCES test_parent (a));
+        INSERT INTO test_parent (a) VALUES (1);
+        "#,
+    )
+    .await
+    .unwrap();
+    conn.execute("INSERT INTO test_child (b) VALUES (1)", [])
+        .await
+        .unwrap();
+    assert!(conn
+        .execute("INSERT INTO test_child (b) VALUES (2)", [])
+        .await
+        .is_err());
+    let row = conn
+        .query_row("PRAGMA journal_mode", [])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.values(), vec![Value::Text("wal".to_owned())]);
+    drop(conn);
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_query_grouped() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch(
+        r#"
+        CREATE TABLE test_line (a INTEGER PRIMARY KEY, b INTEGER NOT NULL, c TEXT NOT NULL);
+        INSERT INTO test_line (b, c) VALUES (1, 'test1'), (2, 'test2'), (1, 'test3');
+        "#,
+    )
+    .await
+    .unwrap();
+    let groups = conn
+        .query_grouped(
+            "SELECT b, c FROM test_line ORDER BY a",
+            [],
+            |row| row.get::<i64>(0),
+            |row| row.get::<String>(1),
+        )
+        .await
+        .unwrap();
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[&1], vec!["test1", "test3"]);
+    assert_eq!(groups[&2], vec!["test2"]);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_close() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute(
+        r#"CREATE TABLE test_tbl (a INTEGER PRIMARY KEY, b TEXT NOT NULL)"#,
+        [],
+    )
+    .await
+    .unwrap();
+    let mut tx = conn.transaction().await.unwrap();
+    tx.execute(r#"INSERT INTO test_tbl (b) VALUES ("test1")"#, &[])
+        .await
+        .unwrap();
+    drop(tx);
+    let row = conn
+        .query_row("SELECT COUNT(*) FROM test_tbl", [])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.values(), vec![Value::Integer(0)]);
+    conn.close().await.unwrap();
+    // Dropping without closing must not panic either.
+    let conn = Connection::open(":memory:").await.unwrap();
+    drop(conn);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_worker_closed() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    let err = conn
+        .query_transform("SELECT 1", [], |_| -> Result<(), Error> {
+            panic!("worker panic")
+        })
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::WorkerClosed));
+    let err = conn.execute("SELECT 1", []).await.unwrap_err();
+    assert!(matches!(err, Error::WorkerClosed));
+    assert!(matches!(conn.close().await, Err(Error::Join(_))));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_custom_spawner() {
+    let options = ConnectionOptions::new().spawner(|f| {
+        std::thread::Builder::new()
+            .name("sqlite-worker".to_owned())
+            .spawn(f)
+            .unwrap();
+    });
+    let mut conn = Connection::open_with_options(":memory:", options)
+        .await
+        .unwrap();
+    let names = conn
+        .query_transform("SELECT 1", [], |_| {
+            Ok(std::thread::current().name().map(str::to_owned))
+        })
+        .await
+        .unwrap();
+    assert_eq!(names, vec![Some("sqlite-worker".to_owned())]);
+    conn.close().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_query_row_multiple_rows() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch(
+        "CREATE TABLE test_tbl (a INTEGER PRIMARY KEY); \
+        INSERT INTO test_tbl VALUES (1), (2);",
+    )
+    .await
+    .unwrap();
+    let err = conn
+        .query_row("SELECT a FROM test_tbl", [])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::MultipleRows));
+    let row = conn
+        .query_row("SELECT a FROM test_tbl WHERE a = 1", [])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.values(), &[Value::Integer(1)]);
+    let row = conn
+        .query_row("SELECT a FROM test_tbl WHERE a = 3", [])
+        .await
+        .unwrap();
+    assert!(row.is_none());
+    let mut tx = conn.transaction().await.unwrap();
+    let err = tx
+        .query_row("SELECT a FROM test_tbl", [])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::MultipleRows));
+    tx.rollback().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_queue_wait() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    let status = conn
+        .execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    assert!(status.metrics().queue_wait() < Duration::from_millis(200));
+    // The slow command keeps running on the worker after the caller gives up.
+    let slow = conn.query_transform("SELECT 1", [], |_| {
+        std::thread::sleep(Duration::from_millis(500));
+        Ok(())
+    });
+    assert!(tokio::time::timeout(Duration::from_millis(50), slow)
+        .await
+        .is_err());
+    let status = conn
+        .execute("INSERT INTO test_tbl VALUES (1)", [])
+        .await
+        .unwrap();
+    assert!(status.metrics().queue_wait() >= Duration::from_millis(300));
+    let rows = conn.query("SELECT a FROM test_tbl", []).await.unwrap();
+    assert!(rows.metrics().queue_wait() < Duration::from_millis(200));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_query_buffered() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    let mut rows = conn
+        .query_buffered(
+            "WITH RECURSIVE seq(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM seq WHERE n < 100000) \
+            SELECT n FROM seq",
+            [],
+            256,
+        )
+        .await
+        .unwrap();
+    let mut expected = 1;
+    while let Some(row) = rows.next().await {
+        assert_eq!(row.unwrap().get::<i64>(0).unwrap(), expected);
+        expected += 1;
+    }
+    assert_eq!(expected, 100001);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_schema_version() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    let version = conn.schema_version().await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    let created = conn.schema_version().await.unwrap();
+    assert!(created > version);
+    conn.execute("ALTER TABLE test_tbl ADD COLUMN b TEXT", [])
+        .await
+        .unwrap();
+    let altered = conn.schema_version().await.unwrap();
+    assert!(altered > created);
+    conn.execute("INSERT INTO test_tbl VALUES (1, 'a')", [])
+        .await
+        .unwrap();
+    assert_eq!(conn.schema_version().await.unwrap(), altered);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_query_scalar() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch(
+        "CREATE TABLE test_tbl (a INTEGER PRIMARY KEY); \
+        INSERT INTO test_tbl VALUES (1), (2), (3);",
+    )
+    .await
+    .unwrap();
+    let count: Option<i64> = conn
+        .query_scalar("SELECT COUNT(*) FROM test_tbl", [])
+        .await
+        .unwrap();
+    assert_eq!(count, Some(3));
+    let missing: Option<i64> = conn
+        .query_scalar("SELECT a FROM test_tbl WHERE a > 3", [])
+        .await
+        .unwrap();
+    assert_eq!(missing, None);
+    let mut tx = conn.transaction().await.unwrap();
+    let max: Option<i64> = tx
+        .query_scalar("SELECT max(a) FROM test_tbl", [])
+        .await
+        .unwrap();
+    assert_eq!(max, Some(3));
+    let err = tx
+        .query_scalar::<i64, _, _>("SELECT a FROM test_tbl", [])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::MultipleRows));
+    tx.commit().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_execute_many_columnar() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch(
+        "CREATE TABLE test_rows (a INTEGER, b TEXT); \
+        CREATE TABLE test_cols (a INTEGER, b TEXT);",
+    )
+    .await
+    .unwrap();
+    let rows: Vec<Vec<Value>> = (0..10)
+        .map(|i| vec![Value::Integer(i), Value::Text(format!("v{i}"))])
+        .collect();
+    let mut tx = conn.transaction().await.unwrap();
+    for row in &rows {
+        tx.execute("INSERT INTO test_rows VALUES ($1, $2)", row)
+            .await
+            .unwrap();
+    }
+    let columns = vec![
+        rows.iter().map(|v| v[0].clone()).collect(),
+        rows.iter().map(|v| v[1].clone()).collect(),
+    ];
+    let status = tx
+        .execute_many_columnar("INSERT INTO test_cols VALUES ($1, $2)", columns)
+        .await
+        .unwrap();
+    assert_eq!(status.rows_affected(), 10);
+    let err = tx
+        .execute_many_columnar(
+            "INSERT INTO test_cols VALUES ($1, $2)",
+            vec![vec![Value::Integer(1)], vec![]],
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        Error::ColumnLengthMismatch {
+            expected: 1,
+            actual: 0
+        }
+    ));
+    tx.commit().await.unwrap();
+    let mut expected = Vec::new();
+    let mut rows = conn
+        .query("SELECT a, b FROM test_rows ORDER BY rowid", [])
+        .await
+        .unwrap();
+    while let Some(row) = rows.next().await {
+        expected.push(row.unwrap().into_values());
+    }
+    drop(rows);
+    let mut actual = Vec::new();
+    let mut rows = conn
+        .query("SELECT a, b FROM test_cols ORDER BY rowid", [])
+        .await
+        .unwrap();
+    while let Some(row) = rows.next().await {
+        actual.push(row.unwrap().into_values());
+    }
+    assert_eq!(actual, expected);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_query_all() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch(
+        "CREATE TABLE test_tbl (a INTEGER, b TEXT); \
+        INSERT INTO test_tbl VALUES (1, 'a'), (2, 'b'), (3, 'c');",
+    )
+    .await
+    .unwrap();
+    let mut expected = Vec::new();
+    let mut rows = conn
+        .query_buffered("SELECT a, b FROM test_tbl ORDER BY a", [], 16)
+        .await
+        .unwrap();
+    while let Some(row) = rows.next().await {
+        expected.push(row.unwrap().into_values());
+    }
+    drop(rows);
+    let rows = conn
+        .query_all("SELECT a, b FROM test_tbl ORDER BY a", [])
+        .await
+        .unwrap();
+    let actual: Vec<_> = rows.into_iter().map(|v| v.into_values()).collect();
+    assert_eq!(actual, expected);
+    let mut tx = conn.transaction().await.unwrap();
+    let rows = tx
+        .query_all("SELECT a FROM test_tbl WHERE a > 3", [])
+        .await
+        .unwrap();
+    assert!(rows.is_empty());
+    tx.commit().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_transaction_already_open() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute("BEGIN", []).await.unwrap();
+    let err = conn.transaction().await.err().unwrap();
+    assert!(matches!(err, Error::TransactionAlreadyOpen));
+    conn.execute("ROLLBACK", []).await.unwrap();
+    let tx = conn.transaction().await.unwrap();
+    tx.commit().await.unwrap();
+}
+
+#[derive(Debug, PartialEq)]
+struct TestItem {
+    id: i64,
+    name: String,
+}
+
+impl FromRow for TestItem {
+    fn from_row(row: &Row) -> Result<Self, Error> {
+        Ok(Self {
+            id: row.get_by_name("a")?,
+            name: row.get_by_name("b")?,
+        })
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_query_as() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch(
+        "CREATE TABLE test_tbl (a INTEGER, b TEXT); \
+        INSERT INTO test_tbl VALUES (1, 'a'), (2, 'b');",
+    )
+    .await
+    .unwrap();
+    let values: Vec<(i64, String)> = conn
+        .query_as("SELECT a, b FROM test_tbl ORDER BY a", [])
+        .await
+        .unwrap();
+    assert_eq!(values, vec![(1, "a".to_owned()), (2, "b".to_owned())]);
+    let items: Vec<TestItem> = conn
+        .query_as("SELECT a, b FROM test_tbl ORDER BY a", [])
+        .await
+        .unwrap();
+    assert_eq!(
+        items[1],
+        TestItem {
+            id: 2,
+            name: "b".to_owned()
+        }
+    );
+    let err = conn
+        .query_as::<(i64, i64), _, _>("SELECT a, b FROM test_tbl", [])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::Sqlite(_)));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_backup() {
+    let path = temp_path("backup");
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch(
+        "CREATE TABLE test_tbl (a INTEGER, b TEXT); \
+        WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 1000) \
+        INSERT INTO test_tbl SELECT i, printf('item %d', i) FROM n;",
+    )
+    .await
+    .unwrap();
+    let steps = Arc::new(Mutex::new(Vec::new()));
+    let progress = steps.clone();
+    conn.backup_with_progress(&path, move |remaining, total| {
+        progress.lock().unwrap().push((remaining, total));
+    })
+    .await
+    .unwrap();
+    let steps = steps.lock().unwrap().clone();
+    let (remaining, total) = *steps.last().unwrap();
+    assert_eq!(remaining, 0);
+    assert!(total > 0);
+    // The source database remains usable.
+    conn.execute("INSERT INTO test_tbl VALUES (1001, 'item 1001')", [])
+        .await
+        .unwrap();
+    conn.close().await.unwrap();
+    let mut copy = Connection::open(&path).await.unwrap();
+    let count: Option<i64> = copy
+        .query_scalar("SELECT COUNT(*) FROM test_tbl", [])
+        .await
+        .unwrap();
+    assert_eq!(count, Some(1000));
+    let name: Option<String> = copy
+        .query_scalar("SELECT b FROM test_tbl WHERE a = 500", [])
+        .await
+        .unwrap();
+    assert_eq!(name.as_deref(), Some("item 500"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_read_snapshot() {
+    let path = temp_path("read_snapshot");
+    let mut reader = Connection::builder()
+        .path(&path)
+        .journal_mode(JournalMode::Wal)
+        .open()
+        .await
+        .unwrap();
+    reader
+        .execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    let mut writer = Connection::open(&path).await.unwrap();
+    reader.begin_read_snapshot().await.unwrap();
+    writer
+        .execute("INSERT INTO test_tbl VALUES (1)", [])
+        .await
+        .unwrap();
+    let count: Option<i64> = reader
+        .query_scalar("SELECT COUNT(*) FROM test_tbl", [])
+        .await
+        .unwrap();
+    assert_eq!(count, Some(0));
+    let err = reader.begin_read_snapshot().await.unwrap_err();
+    assert!(matches!(err, Error::TransactionAlreadyOpen));
+    reader.end_read_snapshot().await.unwrap();
+    let count: Option<i64> = reader
+        .query_scalar("SELECT COUNT(*) FROM test_tbl", [])
+        .await
+        .unwrap();
+    assert_eq!(count, Some(1));
+    drop(reader);
+    drop(writer);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_wal_frame_count() {
+    let path = temp_path("wal_frame_count");
+    let mut writer = Connection::builder()
+        .path(&path)
+        .journal_mode(JournalMode::Wal)
+        .open()
+        .await
+        .unwrap();
+    writer
+        .execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    // The open snapshot keeps the log from being restarted.
+    let mut reader = Connection::open(&path).await.unwrap();
+    reader.begin_read_snapshot().await.unwrap();
+    let mut counts = Vec::new();
+    for i in 0..3 {
+        writer
+            .execute("INSERT INTO test_tbl VALUES (?1)", [Value::Integer(i)])
+            .await
+            .unwrap();
+        counts.push(writer.wal_frame_count().await.unwrap());
+    }
+    assert!(counts[0] > 0);
+    assert!(counts[0] < counts[1] && counts[1] < counts[2]);
+    reader.end_read_snapshot().await.unwrap();
+    drop(reader);
+    drop(writer);
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    assert_eq!(conn.wal_frame_count().await.unwrap(), 0);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(feature = "hooks")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_wal_hook() {
+    let path = temp_path("wal_hook");
+    let mut conn = Connection::builder()
+        .path(&path)
+        .journal_mode(JournalMode::Wal)
+        .open()
+        .await
+        .unwrap();
+    let mut commits = conn.wal_hook().await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    let (db, frames) = commits.recv().await.unwrap();
+    assert_eq!(db, "main");
+    assert!(frames > 0);
+    let mut tx = conn.transaction().await.unwrap();
+    tx.execute("INSERT INTO test_tbl VALUES (1)", &[])
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+    let (_, next_frames) = commits.recv().await.unwrap();
+    assert!(next_frames > frames);
+    conn.close().await.unwrap();
+    // The hook is removed with the connection.
+    assert!(commits.recv().await.is_none());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(feature = "derive")]
+#[derive(tokio_sqlite::FromRow)]
+struct TestDerived {
+    id: i64,
+    #[sqlite(rename = "b")]
+    name: String,
+    author: Option<i64>,
+}
+
+#[cfg(feature = "derive")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_derive_from_row() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch(
+        "CREATE TABLE test_tbl (id INTEGER, b TEXT, author INTEGER); \
+        INSERT INTO test_tbl VALUES (1, 'a', NULL), (2, 'b', 3);",
+    )
+    .await
+    .unwrap();
+    let items: Vec<TestDerived> = conn
+        .query_as("SELECT id, b, author FROM test_tbl ORDER BY id", [])
+        .await
+        .unwrap();
+    assert_eq!(items[0].id, 1);
+    assert_eq!(items[0].name, "a");
+    assert_eq!(items[0].author, None);
+    assert_eq!(items[1].author, Some(3));
+    let err = conn
+        .query_as::<TestDerived, _, _>("SELECT id, b FROM test_tbl", [])
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, Error::Field { name: "author", .. }));
+}
+
+#[cfg(feature = "derive")]
+#[derive(Clone, Copy, Debug, PartialEq, tokio_sqlite::ToValue, tokio_sqlite::FromValue)]
+enum TestStatus {
+    Draft,
+    Published = 10,
+    Archived,
+}
+
+#[cfg(feature = "derive")]
+#[derive(Clone, Copy, Debug, PartialEq, tokio_sqlite::ToValue, tokio_sqlite::FromValue)]
+#[sqlite(text)]
+enum TestKind {
+    Post,
+    #[sqlite(rename = "page")]
+    Page,
+    Note,
+}
+
+#[cfg(feature = "derive")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_derive_enum_value() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch("CREATE TABLE test_tbl (status INTEGER, kind TEXT)")
+        .await
+        .unwrap();
+    let items = [
+        (TestStatus::Draft, TestKind::Post),
+        (TestStatus::Published, TestKind::Page),
+        (TestStatus::Archived, TestKind::Note),
+    ];
+    for (status, kind) in items {
+        conn.execute(
+            "INSERT INTO test_tbl (status, kind) VALUES (?1, ?2)",
+            [status.into(), kind.into()],
+        )
+        .await
+        .unwrap();
+    }
+    let rows: Vec<(i64, String)> = conn
+        .query_as("SELECT status, kind FROM test_tbl ORDER BY rowid", [])
+        .await
+        .unwrap();
+    assert_eq!(
+        rows,
+        vec![
+            (0, "Post".to_owned()),
+            (10, "page".to_owned()),
+            (11, "Note".to_owned()),
+        ]
+    );
+    let kind: Option<TestKind> = conn
+        .query_scalar(
+            "SELECT kind FROM test_tbl WHERE status = ?1",
+            [TestStatus::Published.into()],
+        )
+        .await
+        .unwrap();
+    assert_eq!(kind, Some(TestKind::Page));
+    let decoded: Vec<(TestStatus, TestKind)> = conn
+        .query_as("SELECT status, kind FROM test_tbl ORDER BY rowid", [])
+        .await
+        .unwrap();
+    assert_eq!(decoded, items);
+    let err = conn
+        .query_scalar::<TestStatus, _, _>("SELECT 5", [])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::Sqlite(_)));
+}
+
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+    records
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_query_to_csv() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch(
+        "CREATE TABLE test_tbl (a INTEGER, b TEXT); \
+        INSERT INTO test_tbl VALUES (1, 'plain'), (2, 'with, comma'), \
+        (3, 'with \"quotes\"'), (4, 'multi\nline'), (5, NULL);",
+    )
+    .await
+    .unwrap();
+    let mut output = Vec::new();
+    conn.query_to_csv(
+        "SELECT a, b FROM test_tbl ORDER BY a",
+        [],
+        &mut output,
+        CsvOptions::new(),
+    )
+    .await
+    .unwrap();
+    let records = parse_csv(&String::from_utf8(output).unwrap());
+    let expected: Vec<Vec<String>> = [
+        ["a", "b"],
+        ["1", "plain"],
+        ["2", "with, comma"],
+        ["3", "with \"quotes\""],
+        ["4", "multi\nline"],
+        ["5", ""],
+    ]
+    .iter()
+    .map(|v| v.iter().map(|v| v.to_string()).collect())
+    .collect();
+    assert_eq!(records, expected);
+    let mut output = Vec::new();
+    conn.query_to_csv(
+        "SELECT a FROM test_tbl WHERE a = 1",
+        [],
+        &mut output,
+        CsvOptions::new().header(false),
+    )
+    .await
+    .unwrap();
+    assert_eq!(output, b"1\r\n");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_transaction_savepoint() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    let mut tx = conn.transaction().await.unwrap();
+    tx.execute("INSERT INTO test_tbl VALUES (1)", &[])
+        .await
+        .unwrap();
+    let mut savepoint = tx.savepoint().await.unwrap();
+    savepoint
+        .execute("INSERT INTO test_tbl VALUES (2)", &[])
+        .await
+        .unwrap();
+    let mut nested = savepoint.savepoint().await.unwrap();
+    nested
+        .execute("INSERT INTO test_tbl VALUES (3)", &[])
+        .await
+        .unwrap();
+    nested.commit().await.unwrap();
+    savepoint.rollback().await.unwrap();
+    let mut savepoint = tx.savepoint().await.unwrap();
+    savepoint
+        .execute("INSERT INTO test_tbl VALUES (4)", &[])
+        .await
+        .unwrap();
+    let mut nested = savepoint.savepoint().await.unwrap();
+    nested
+        .execute("INSERT INTO test_tbl VALUES (5)", &[])
+        .await
+        .unwrap();
+    // Dropped savepoints are rolled back.
+    drop(nested);
+    savepoint.commit().await.unwrap();
+    let mut savepoint = tx.savepoint().await.unwrap();
+    savepoint
+        .execute("INSERT INTO test_tbl VALUES (6)", &[])
+        .await
+        .unwrap();
+    drop(savepoint);
+    tx.commit().await.unwrap();
+    let rows = conn
+        .query_as::<(i64,), _, _>("SELECT a FROM test_tbl ORDER BY a", [])
+        .await
+        .unwrap();
+    assert_eq!(rows, vec![(1,), (4,)]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_ip_addresses() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (a, b)", [])
+        .await
+        .unwrap();
+    let v4 = Ipv4Addr::new(192, 168, 0, 1);
+    let v6 = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+    for format in [IpFormat::Text, IpFormat::Blob] {
+        conn.execute(
+            "INSERT INTO test_tbl VALUES ($1, $2)",
+            [ip_value(v4, format), ip_value(v6, format)],
+        )
+        .await
+        .unwrap();
+    }
+    let rows = conn
+        .query_all("SELECT a, b FROM test_tbl", [])
+        .await
+        .unwrap();
+    assert_eq!(rows.len(), 2);
+    for row in rows {
+        assert_eq!(row.get::<Ipv4Addr>(0).unwrap(), v4);
+        assert_eq!(row.get::<IpAddr>(0).unwrap(), IpAddr::V4(v4));
+        assert_eq!(row.get::<Ipv6Addr>(1).unwrap(), v6);
+        assert_eq!(row.get::<IpAddr>(1).unwrap(), IpAddr::V6(v6));
+        assert!(row.get::<Ipv6Addr>(0).is_err());
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_transaction_with_behavior() {
+    let path = temp_path("transaction_with_behavior");
+    let mut conn = Connection::open(&path).await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    let mut writer = Connection::builder()
+        .path(&path)
+        .busy_timeout(Duration::from_secs(5))
+        .open()
+        .await
+        .unwrap();
+    let tx = conn
+        .transaction_with_behavior(TransactionBehavior::Immediate)
+        .await
+        .unwrap();
+    let write = tokio::spawn(async move {
+        writer
+            .execute("INSERT INTO test_tbl VALUES (1)", [])
+            .await
+            .map(|_| writer)
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!write.is_finished());
+    tx.commit().await.unwrap();
+    let writer = write.await.unwrap().unwrap();
+    drop(writer);
+    let count: Option<i64> = conn
+        .query_scalar("SELECT COUNT(*) FROM test_tbl", [])
+        .await
+        .unwrap();
+    assert_eq!(count, Some(1));
+    drop(conn);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_checkpoint_on_idle() {
+    let path = temp_path("checkpoint_on_idle");
+    let options = ConnectionOptions::new()
+        .journal_mode(JournalMode::Wal)
+        .checkpoint_on_idle(Duration::from_millis(100));
+    let mut conn = Connection::open_with_options(&path, options).await.unwrap();
+    conn.execute_batch(
+        "PRAGMA wal_autocheckpoint = 0; \
+        CREATE TABLE test_tbl (a BLOB); \
+        WITH RECURSIVE seq(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM seq WHERE n < 100) \
+        INSERT INTO test_tbl SELECT randomblob(4096) FROM seq;",
+    )
+    .await
+    .unwrap();
+    // Without checkpoints all written pages stay in the WAL.
+    let size = std::fs::metadata(&path).unwrap().len();
+    assert!(size < 4096 * 100);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let size = std::fs::metadata(&path).unwrap().len();
+    assert!(size >= 4096 * 100);
+    drop(conn);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_transaction_scope() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER NOT NULL)", [])
+        .await
+        .unwrap();
+    let result = conn
+        .transaction_scope(|tx| {
+            Box::pin(async move {
+                tx.execute("INSERT INTO test_tbl VALUES (1)", &[]).await?;
+                tx.execute("INSERT INTO test_tbl VALUES (NULL)", &[])
+                    .await?;
+                Ok(())
+            })
+        })
+        .await;
+    assert!(result.is_err());
+    let count: Option<i64> = conn
+        .query_scalar("SELECT COUNT(*) FROM test_tbl", [])
+        .await
+        .unwrap();
+    assert_eq!(count, Some(0));
+    let id = conn
+        .transaction_scope(|tx| {
+            Box::pin(async move {
+                let status = tx.execute("INSERT INTO test_tbl VALUES (2)", &[]).await?;
+                Ok(status.last_insert_id())
+            })
+        })
+        .await
+        .unwrap();
+    assert_eq!(id, Some(1));
+    let count: Option<i64> = conn
+        .query_scalar("SELECT COUNT(*) FROM test_tbl", [])
+        .await
+        .unwrap();
+    assert_eq!(count, Some(1));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_parameter_names() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    let names = conn
+        .parameter_names("SELECT :first, ?, @second")
+        .await
+        .unwrap();
+    assert_eq!(
+        names,
+        vec![Some(":first".to_owned()), None, Some("@second".to_owned())]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_execute_many() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER NOT NULL)", [])
+        .await
+        .unwrap();
+    let rows_affected = conn
+        .execute_many(
+            "INSERT INTO test_tbl VALUES ($1)",
+            (0..1000).map(|i| vec![Value::Integer(i)]),
+        )
+        .await
+        .unwrap();
+    assert_eq!(rows_affected, 1000);
+    let count: Option<i64> = conn
+        .query_scalar("SELECT COUNT(*) FROM test_tbl", [])
+        .await
+        .unwrap();
+    assert_eq!(count, Some(1000));
+    let err = conn
+        .execute_many(
+            "INSERT INTO test_tbl VALUES ($1)",
+            vec![vec![Value::Integer(1)], vec![Value::Null]],
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::ArgumentSet { index: 1, .. }));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_insert_many() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch(
+        "CREATE TABLE test_tbl (a TEXT NOT NULL); \
+        INSERT INTO test_tbl VALUES ('first');",
+    )
+    .await
+    .unwrap();
+    let (first, last) = conn
+        .insert_many(
+            "INSERT INTO test_tbl VALUES ($1)",
+            ["a", "b", "c"].map(|v| vec![Value::Text(v.to_owned())]),
+        )
+        .await
+        .unwrap();
+    assert_eq!((first, last), (2, 4));
+    let values: Vec<(String,)> = conn
+        .query_as(
+            "SELECT a FROM test_tbl WHERE rowid BETWEEN $1 AND $2 ORDER BY rowid",
+            [Value::Integer(first), Value::Integer(last)],
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        values,
+        vec![("a".to_owned(),), ("b".to_owned(),), ("c".to_owned(),)]
+    );
+    // Failed inserts are rolled back.
+    let err = conn
+        .insert_many(
+            "INSERT INTO test_tbl VALUES ($1)",
+            vec![vec![Value::Text("d".to_owned())], vec![Value::Null]],
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::ArgumentSet { index: 1, .. }));
+    let count: Option<i64> = conn
+        .query_scalar("SELECT COUNT(*) FROM test_tbl", [])
+        .await
+        .unwrap();
+    assert_eq!(count, Some(4));
+}
+
+#[cfg(feature = "serde_json")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_rows_json_objects() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch(
+        "CREATE TABLE test_tbl (a INTEGER, b TEXT, c REAL); \
+        INSERT INTO test_tbl VALUES (1, 'a', 1.5), (2, NULL, NULL);",
+    )
+    .await
+    .unwrap();
+    let rows = conn
+        .query("SELECT a, b, c FROM test_tbl ORDER BY a", [])
+        .await
+        .unwrap();
+    let objects: Vec<_> = rows.json_objects().collect().await;
+    let objects: Vec<_> = objects.into_iter().map(|v| v.unwrap()).collect();
+    assert_eq!(
+        serde_json::Value::Array(objects),
+        serde_json::json!([
+            {"a": 1, "b": "a", "c": 1.5},
+            {"a": 2, "b": null, "c": null},
+        ])
+    );
+}
+
+#[cfg(feature = "serde_json")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_row_to_json_object() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    let row = conn
+        .query_row(
+            "SELECT NULL AS n, 42 AS i, 2.5 AS r, 'text' AS t, X'00FF10' AS b, X'666F6F62' AS p",
+            [],
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        row.to_json_object(),
+        serde_json::json!({
+            "n": null,
+            "i": 42,
+            "r": 2.5,
+            "t": "text",
+            "b": "AP8Q",
+            "p": "Zm9vYg==",
+        })
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_prepared_statement() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER, b TEXT)", [])
+        .await
+        .unwrap();
+    let mut insert = conn
+        .prepare("INSERT INTO test_tbl VALUES ($1, $2)")
+        .await
+        .unwrap();
+    for i in 0..5 {
+        let status = insert
+            .execute([Value::Integer(i), Value::Text(format!("v{i}"))])
+            .await
+            .unwrap();
+        assert_eq!(status.rows_affected(), 1);
+    }
+    let mut select = conn
+        .prepare("SELECT b FROM test_tbl WHERE a = $1")
+        .await
+        .unwrap();
+    for i in [4, 0, 2] {
+        let mut rows = select.query([Value::Integer(i)]).await.unwrap();
+        let row = rows.next().await.unwrap().unwrap();
+        assert_eq!(row.get::<String>(0).unwrap(), format!("v{i}"));
+        assert!(rows.next().await.is_none());
+    }
+    assert!(conn.prepare("SELECT FROM").await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_transaction_with_raw() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    let mut tx = conn.transaction().await.unwrap();
+    let mut savepoint = tx.savepoint().await.unwrap();
+    let mut nested = savepoint.savepoint().await.unwrap();
+    nested
+        .with_raw(|conn| {
+            let mut stmt = conn.prepare("INSERT INTO test_tbl VALUES (?1)")?;
+            for i in 0..3 {
+                stmt.execute([i])?;
+            }
+            Ok(())
+        })
+        .await
+        .unwrap();
+    nested.rollback().await.unwrap();
+    let count = savepoint
+        .with_raw(|conn| {
+            Ok(conn.query_row("SELECT COUNT(*) FROM test_tbl", [], |row| {
+                row.get::<_, i64>(0)
+            })?)
+        })
+        .await
+        .unwrap();
+    assert_eq!(count, 0);
+    savepoint
+        .with_raw(|conn| Ok(conn.execute("INSERT INTO test_tbl VALUES (10)", [])?))
+        .await
+        .unwrap();
+    savepoint.commit().await.unwrap();
+    tx.commit().await.unwrap();
+    let rows = conn
+        .query_as::<(i64,), _, _>("SELECT a FROM test_tbl", [])
+        .await
+        .unwrap();
+    assert_eq!(rows, vec![(10,)]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_statement_cache() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.set_statement_cache_capacity(4).await.unwrap();
+    conn.execute_batch(
+        "CREATE TABLE test_tbl (a INTEGER, b TEXT); \
+        INSERT INTO test_tbl VALUES (1, 'a'), (2, 'b'), (3, 'c');",
+    )
+    .await
+    .unwrap();
+    for i in 0..100 {
+        let id = i % 3 + 1;
+        let mut rows = conn
+            .query("SELECT b FROM test_tbl WHERE a = $1", [Value::Integer(id)])
+            .await
+            .unwrap();
+        let row = rows.next().await.unwrap().unwrap();
+        let expected = ["a", "b", "c"][(id - 1) as usize];
+        assert_eq!(row.get::<String>(0).unwrap(), expected);
+        // Dropping unfinished rows must not leave bindings in the cache.
+        drop(rows);
+        let status = conn
+            .execute(
+                "UPDATE test_tbl SET b = b WHERE a = $1",
+                [Value::Integer(id)],
+            )
+            .await
+            .unwrap();
+        assert_eq!(status.rows_affected(), 1);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_statement_info() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER, b TEXT)", [])
+        .await
+        .unwrap();
+    let info = conn
+        .statement_info("SELECT a, b FROM test_tbl WHERE a = $1")
+        .await
+        .unwrap();
+    assert!(info.readonly());
+    assert_eq!(info.column_count(), 2);
+    assert_eq!(info.parameter_count(), 1);
+    assert!(!info.explain());
+    let info = conn
+        .statement_info("INSERT INTO test_tbl VALUES ($1, $2)")
+        .await
+        .unwrap();
+    assert!(!info.readonly());
+    assert_eq!(info.column_count(), 0);
+    assert_eq!(info.parameter_count(), 2);
+    let info = conn
+        .statement_info("EXPLAIN QUERY PLAN SELECT a FROM test_tbl")
+        .await
+        .unwrap();
+    assert!(info.explain());
+    let info = conn
+        .statement_info("-- plan\n /* of */ explain SELECT a FROM test_tbl")
+        .await
+        .unwrap();
+    assert!(info.explain());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_create_scalar_function() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.create_scalar_function("double", 1, |args| match &args[0] {
+        Value::Integer(v) => Ok(Value::Integer(v * 2)),
+        _ => Ok(Value::Null),
+    })
+    .await
+    .unwrap();
+    let value: Option<i64> = conn.query_scalar("SELECT double(21)", []).await.unwrap();
+    assert_eq!(value, Some(42));
+    // Registering the same name replaces the function.
+    conn.create_scalar_function("double", 1, |args| match &args[0] {
+        Value::Integer(v) => Ok(Value::Integer(v + v + 1)),
+        _ => Ok(Value::Null),
+    })
+    .await
+    .unwrap();
+    let value: Option<i64> = conn.query_scalar("SELECT double(21)", []).await.unwrap();
+    assert_eq!(value, Some(43));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_close_commands_lost() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    let (started_tx, mut started_rx) = tokio::sync::mpsc::unbounded_channel();
+    let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+    let blocked = conn.query_transform("SELECT 1", [], move |_| -> Result<(), Error> {
+        started_tx.send(()).unwrap();
+        let _ = release_rx.recv();
+        panic!("worker panic")
+    });
+    // Polling once sends the command without waiting for the result.
+    assert!(tokio::time::timeout(Duration::ZERO, blocked).await.is_err());
+    started_rx.recv().await.unwrap();
+    // The command is queued behind the blocked one and nobody waits for it.
+    let queued = conn.execute("CREATE TABLE test_tbl (a INTEGER)", []);
+    assert!(tokio::time::timeout(Duration::ZERO, queued).await.is_err());
+    drop(release_tx);
+    assert!(matches!(
+        conn.close().await,
+        Err(Error::CommandsLost { count: 1 })
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_blob_io() {
+    const SIZE: usize = 1 << 20;
+    const CHUNK: usize = 64 << 10;
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch("CREATE TABLE test_tbl (data BLOB)")
+        .await
+        .unwrap();
+    let status = conn
+        .execute(
+            "INSERT INTO test_tbl (data) VALUES (zeroblob(?1))",
+            [Value::Integer(SIZE as i64)],
+        )
+        .await
+        .unwrap();
+    let rowid = status.last_insert_id().unwrap();
+    let data: Vec<u8> = (0..SIZE).map(|i| (i % 251) as u8).collect();
+    let mut blob = conn
+        .blob_open("main", "test_tbl", "data", rowid, false)
+        .await
+        .unwrap();
+    assert_eq!(blob.size().await.unwrap(), SIZE);
+    blob.write_at(&data, 0).await.unwrap();
+    // Blobs cannot be extended by writes.
+    assert!(blob.write_at(&[0; 2], SIZE - 1).await.is_err());
+    drop(blob);
+    let mut blob = conn
+        .blob_open("main", "test_tbl", "data", rowid, true)
+        .await
+        .unwrap();
+    let mut buf = vec![0; CHUNK];
+    for offset in (0..SIZE).step_by(CHUNK) {
+        assert_eq!(blob.read_at(&mut buf, offset).await.unwrap(), CHUNK);
+        assert_eq!(buf, data[offset..offset + CHUNK]);
+    }
+    // Reads past the end of the blob are short.
+    assert_eq!(blob.read_at(&mut buf, SIZE - 10).await.unwrap(), 10);
+    assert_eq!(buf[..10], data[SIZE - 10..]);
+    assert_eq!(blob.read_at(&mut buf, SIZE).await.unwrap(), 0);
+    drop(blob);
+    // The connection serves other commands once the blob is dropped.
+    let len: Option<i64> = conn
+        .query_scalar("SELECT length(data) FROM test_tbl", [])
+        .await
+        .unwrap();
+    assert_eq!(len, Some(SIZE as i64));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_interrupt_query() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    let interrupt = conn.interrupt_handle();
+    let task = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        interrupt.interrupt();
+    });
+    let mut rows = conn
+        .query(
+            "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c) \
+            SELECT COUNT(*) FROM c",
+            [],
+        )
+        .await
+        .unwrap();
+    let err = rows.next().await.unwrap().unwrap_err();
+    assert!(matches!(err, Error::Sqlite(_)));
+    drop(rows);
+    task.await.unwrap();
+    // The connection remains usable after the interruption.
+    let value: Option<i64> = conn.query_scalar("SELECT 1", []).await.unwrap();
+    assert_eq!(value, Some(1));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_retry_predicate() {
+    fn flaky(calls: Arc<AtomicUsize>) -> impl Fn(&[Value]) -> Result<Value, Error> + Send {
+        move |_| match calls.fetch_add(1, Ordering::Relaxed) {
+            0 => Err(Error::Io(std::io::Error::other("flaky failure"))),
+            _ => Ok(Value::Integer(1)),
+        }
+    }
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    conn.create_scalar_function("flaky", 0, flaky(calls.clone()))
+        .await
+        .unwrap();
+    // Errors of user functions are not retried by default.
+    let err = conn
+        .execute_with_retry(
+            "INSERT INTO test_tbl VALUES (flaky())",
+            [],
+            RetryPolicy::new(3, Duration::ZERO),
+        )
+        .await
+        .unwrap_err();
+    assert!(!err.is_busy());
+    let options = ConnectionOptions::new().retry_predicate(|err| match err {
+        Error::Sqlite(err) => err.sqlite_error_code() == Some(rusqlite::ErrorCode::Unknown),
+        _ => false,
+    });
+    let mut conn = Connection::open_with_options(":memory:", options)
+        .await
+        .unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    calls.store(0, Ordering::Relaxed);
+    conn.create_scalar_function("flaky", 0, flaky(calls.clone()))
+        .await
+        .unwrap();
+    conn.execute_with_retry(
+        "INSERT INTO test_tbl VALUES (flaky())",
+        [],
+        RetryPolicy::new(3, Duration::ZERO),
+    )
+    .await
+    .unwrap();
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+    calls.store(0, Ordering::Relaxed);
+    conn.transaction_retry(3, |tx| {
+        Box::pin(async move {
+            tx.execute("INSERT INTO test_tbl VALUES (2)", &[]).await?;
+            tx.execute("INSERT INTO test_tbl VALUES (flaky())", &[])
+                .await
+        })
+    })
+    .await
+    .unwrap();
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+    // The failed attempt of the transaction is rolled back.
+    let count: Option<i64> = conn
+        .query_scalar("SELECT COUNT(*) FROM test_tbl", [])
+        .await
+        .unwrap();
+    assert_eq!(count, Some(3));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_query_timeout() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    // The first step never finishes, so no rows are produced in time.
+    let err = conn
+        .query_timeout(
+            "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c) \
+            SELECT COUNT(*) FROM c",
+            [],
+            Duration::from_millis(100),
+        )
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, Error::Timeout));
+    // The interrupted worker is available for the next query.
+    let mut rows = conn
+        .query_timeout(
+            "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c WHERE x < 1000) \
+            SELECT x FROM c",
+            [],
+            Duration::from_secs(10),
+        )
+        .await
+        .unwrap();
+    let mut count = 0;
+    while let Some(row) = rows.next().await {
+        count += 1;
+        assert_eq!(row.unwrap().get::<i64>(0).unwrap(), count);
+    }
+    assert_eq!(count, 1000);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_total_changes() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    assert_eq!(conn.total_changes().await.unwrap(), 0);
+    assert_eq!(conn.last_insert_rowid().await.unwrap(), 0);
+    for i in 1..=3 {
+        conn.execute("INSERT INTO test_tbl VALUES (?1)", [Value::Integer(i)])
+            .await
+            .unwrap();
+        assert_eq!(conn.total_changes().await.unwrap(), i);
+    }
+    assert_eq!(conn.last_insert_rowid().await.unwrap(), 3);
+    let mut tx = conn.transaction().await.unwrap();
+    tx.execute("UPDATE test_tbl SET a = a + 1", &[])
+        .await
+        .unwrap();
+    tx.rollback().await.unwrap();
+    assert_eq!(conn.total_changes().await.unwrap(), 6);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_is_autocommit() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    assert!(conn.is_autocommit().await.unwrap());
+    let mut tx = conn.transaction().await.unwrap();
+    assert!(!tx.is_autocommit().await.unwrap());
+    tx.commit().await.unwrap();
+    assert!(conn.is_autocommit().await.unwrap());
+    conn.execute_batch("BEGIN").await.unwrap();
+    assert!(!conn.is_autocommit().await.unwrap());
+    conn.execute_batch("ROLLBACK").await.unwrap();
+    assert!(conn.is_autocommit().await.unwrap());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_into_values() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute(
+        "CREATE TABLE test_tbl (a INTEGER, b INTEGER, c REAL, d TEXT, e BLOB, f INTEGER, g INTEGER)",
+        [],
+    )
+    .await
+    .unwrap();
+    conn.execute(
+        "INSERT INTO test_tbl VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        tokio_sqlite::values![
+            true,
+            42u32,
+            1.5f32,
+            "x",
+            &b"\x01\x02"[..],
+            None::<u64>,
+            u64::MAX,
+        ],
+    )
+    .await
+    .unwrap();
+    let row = conn
+        .query_row("SELECT a, b, c, d, e, f, g FROM test_tbl", [])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.values()[0], Value::Integer(1));
+    assert!(row.get::<bool>(0).unwrap());
+    assert_eq!(row.get::<u32>(1).unwrap(), 42);
+    assert_eq!(row.get::<f32>(2).unwrap(), 1.5);
+    assert_eq!(row.get::<String>(3).unwrap(), "x");
+    assert_eq!(row.get::<Vec<u8>>(4).unwrap(), vec![1, 2]);
+    assert_eq!(row.get::<Option<u64>>(5).unwrap(), None);
+    // Unsigned integers above `i64::MAX` wrap around.
+    assert_eq!(row.get::<i64>(6).unwrap() as u64, u64::MAX);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_random_seed() {
+    const QUERY: &str = "SELECT random(), randomblob(12) FROM (SELECT 1 UNION ALL SELECT 2)";
+    let mut results = Vec::new();
+    for seed in [42, 42, 7] {
+        let options = ConnectionOptions::new().random_seed(seed);
+        let mut conn = Connection::open_with_options(":memory:", options)
+            .await
+            .unwrap();
+        let rows: Vec<(i64, Vec<u8>)> = conn.query_as(QUERY, []).await.unwrap();
+        assert_eq!(rows[0].1.len(), 12);
+        assert_ne!(rows[0], rows[1]);
+        results.push(rows);
+    }
+    assert_eq!(results[0], results[1]);
+    assert_ne!(results[0], results[2]);
+}
+
+#[cfg(feature = "hooks")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_update_hook() {
+    use tokio_sqlite::Action;
+
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    conn.update_hook(Some(move |action, db: &str, table: &str, rowid| {
+        let _ = tx.send((action, db.to_owned(), table.to_owned(), rowid));
+    }))
+    .await
+    .unwrap();
+    let rollbacks = Arc::new(AtomicUsize::new(0));
+    let counter = rollbacks.clone();
+    conn.rollback_hook(Some(move || {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }))
+    .await
+    .unwrap();
+    conn.execute("INSERT INTO test_tbl VALUES (1), (2)", [])
+        .await
+        .unwrap();
+    conn.execute("DELETE FROM test_tbl WHERE a = 1", [])
+        .await
+        .unwrap();
+    let mut events = Vec::new();
+    while let Ok(event) = rx.try_recv() {
+        events.push(event);
+    }
+    let events: Vec<_> = events
+        .into_iter()
+        .map(|(action, db, table, rowid)| {
+            assert_eq!((db.as_str(), table.as_str()), ("main", "test_tbl"));
+            (action, rowid)
+        })
+        .collect();
+    assert_eq!(
+        events,
+        vec![
+            (Action::SQLITE_INSERT, 1),
+            (Action::SQLITE_INSERT, 2),
+            (Action::SQLITE_DELETE, 1),
+        ]
+    );
+    // Commits rejected by the commit hook are rolled back.
+    conn.commit_hook(Some(|| true)).await.unwrap();
+    assert!(conn
+        .execute("INSERT INTO test_tbl VALUES (3)", [])
+        .await
+        .is_err());
+    assert_eq!(rollbacks.load(Ordering::Relaxed), 1);
+    assert_eq!(rx.try_recv().unwrap().0, Action::SQLITE_INSERT);
+    conn.commit_hook(None::<fn() -> bool>).await.unwrap();
+    conn.update_hook(None::<fn(Action, &str, &str, i64)>)
+        .await
+        .unwrap();
+    conn.execute("INSERT INTO test_tbl VALUES (4)", [])
+        .await
+        .unwrap();
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_rows_drop_resets_statement() {
+    const QUERY: &str = "SELECT a FROM test_tbl ORDER BY a";
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    conn.execute_many(
+        "INSERT INTO test_tbl VALUES ($1)",
+        (0..100).map(|i| vec![Value::Integer(i)]),
+    )
+    .await
+    .unwrap();
+    for _ in 0..2 {
+        let mut rows = conn.query(QUERY, []).await.unwrap();
+        let row = rows.next().await.unwrap().unwrap();
+        assert_eq!(row.get::<i64>(0).unwrap(), 0);
+    }
+    // The cached statement starts from the first row again.
+    let values: Vec<(i64,)> = conn.query_as(QUERY, []).await.unwrap();
+    assert_eq!(values.len(), 100);
+    assert_eq!(values[0], (0,));
+    let mut rows = conn.query(QUERY, []).await.unwrap();
+    rows.next().await.unwrap().unwrap();
+    drop(rows);
+    // A statement left running would lock the table.
+    conn.execute("DROP TABLE test_tbl", []).await.unwrap();
+}
+
+#[cfg(feature = "load_extension")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_load_extension_missing() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    let path = temp_path("missing_extension");
+    let err = unsafe { conn.load_extension(&path, None).await }.unwrap_err();
+    assert!(matches!(err, Error::Sqlite(_)));
+    // Loading is disabled again after the attempt.
+    let err = conn
+        .query_row(
+            "SELECT load_extension(?1)",
+            [Value::Text(path.display().to_string())],
+        )
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("not authorized"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_query_maps() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch(
+        "CREATE TABLE test_tbl (a INTEGER, b TEXT, c REAL); \
+        INSERT INTO test_tbl VALUES (1, 'x', 1.5), (2, NULL, 2.5);",
+    )
+    .await
+    .unwrap();
+    let maps = conn
+        .query_maps("SELECT a, b, c FROM test_tbl ORDER BY a", [])
+        .await
+        .unwrap();
+    assert_eq!(maps.len(), 2);
+    assert_eq!(
+        maps[0],
+        HashMap::from([
+            ("a".to_owned(), Value::Integer(1)),
+            ("b".to_owned(), Value::Text("x".to_owned())),
+            ("c".to_owned(), Value::Real(1.5)),
+        ])
+    );
+    assert_eq!(maps[1]["b"], Value::Null);
+    assert_eq!(maps[1]["c"], Value::Real(2.5));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_create_collation() {
+    const QUERY: &str = "SELECT a FROM test_tbl ORDER BY a COLLATE reverse";
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch(
+        "CREATE TABLE test_tbl (a TEXT); \
+        INSERT INTO test_tbl VALUES ('b'), ('c'), ('a');",
+    )
+    .await
+    .unwrap();
+    conn.create_collation("reverse", |a, b| b.cmp(a))
+        .await
+        .unwrap();
+    let values: Vec<(String,)> = conn.query_as(QUERY, []).await.unwrap();
+    assert_eq!(values, [("c",), ("b",), ("a",)].map(|(v,)| (v.to_owned(),)));
+    let values: Vec<(String,)> = conn
+        .query_as("SELECT a FROM test_tbl ORDER BY a", [])
+        .await
+        .unwrap();
+    assert_eq!(values, [("a",), ("b",), ("c",)].map(|(v,)| (v.to_owned(),)));
+    // Registering the same name replaces the collation.
+    conn.create_collation("reverse", |a, b| a.cmp(b))
+        .await
+        .unwrap();
+    let values: Vec<(String,)> = conn.query_as(QUERY, []).await.unwrap();
+    assert_eq!(values[0].0, "a");
+    conn.remove_collation("reverse").await.unwrap();
+    assert!(conn.query_all(QUERY, []).await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_query_bounded() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    let rows = conn
+        .query_bounded("SELECT 1, 'abc' UNION ALL SELECT 2, 'def'", [], 2, 22)
+        .await
+        .unwrap();
+    assert_eq!(rows.len(), 2);
+    let err = conn
+        .query_bounded("SELECT 1, 'abc' UNION ALL SELECT 2, 'def'", [], 2, 21)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        Error::ResultTooLarge {
+            max_rows: 2,
+            max_bytes: 21
+        }
+    ));
+    // The endless query is stopped once the limit is exceeded.
+    let err = tokio::time::timeout(
+        Duration::from_secs(10),
+        conn.query_bounded(
+            "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c) SELECT x FROM c",
+            [],
+            100,
+            usize::MAX,
+        ),
+    )
+    .await
+    .unwrap()
+    .unwrap_err();
+    assert!(matches!(err, Error::ResultTooLarge { max_rows: 100, .. }));
+    let value: Option<i64> = conn.query_scalar("SELECT 1", []).await.unwrap();
+    assert_eq!(value, Some(1));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_durations() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER, b INTEGER)", [])
+        .await
+        .unwrap();
+    let duration = Duration::from_secs(5 * 3600) + Duration::from_nanos(123_456_789);
+    conn.execute(
+        "INSERT INTO test_tbl VALUES (?1, ?2)",
+        [
+            duration_value(duration, DurationUnit::Nanoseconds).unwrap(),
+            duration_value(duration, DurationUnit::Seconds).unwrap(),
+        ],
+    )
+    .await
+    .unwrap();
+    let row = conn
+        .query_row("SELECT a, b, -1 FROM test_tbl", [])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        row.get_duration(0, DurationUnit::Nanoseconds).unwrap(),
+        duration
+    );
+    // The remainder smaller than the unit is truncated.
+    assert_eq!(
+        row.get_duration(1, DurationUnit::Seconds).unwrap(),
+        Duration::from_secs(5 * 3600)
+    );
+    assert!(row.get_duration(2, DurationUnit::Seconds).is_err());
+    assert!(duration_value(Duration::MAX, DurationUnit::Nanoseconds).is_err());
+    let max = Duration::from_secs(i64::MAX as u64);
+    assert!(duration_value(max, DurationUnit::Seconds).is_ok());
+    assert!(duration_value(max, DurationUnit::Milliseconds).is_err());
+    // Durations beyond the nanosecond range are read in the stored unit.
+    let row = conn
+        .query_row(
+            "SELECT ?1",
+            [duration_value(max, DurationUnit::Seconds).unwrap()],
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(row.get_duration(0, DurationUnit::Seconds).unwrap(), max);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_reference_db() {
+    let main_path = temp_path("reference_main");
+    let reference_path = temp_path("reference_ref");
+    let mut reference = Connection::open(&reference_path).await.unwrap();
+    reference
+        .execute_batch(
+            "CREATE TABLE countries (code TEXT, name TEXT); \
+            INSERT INTO countries VALUES ('FR', 'France'), ('JP', 'Japan');",
+        )
+        .await
+        .unwrap();
+    reference.close().await.unwrap();
+    let mut conn = Connection::with_reference_db(&main_path, &reference_path)
+        .await
+        .unwrap();
+    conn.execute("CREATE TABLE users (name TEXT, country TEXT)", [])
+        .await
+        .unwrap();
+    conn.execute("INSERT INTO users VALUES ('alice', 'JP')", [])
+        .await
+        .unwrap();
+    let name: Option<String> = conn
+        .query_scalar(
+            "SELECT countries.name FROM users JOIN countries ON code = country",
+            [],
+        )
+        .await
+        .unwrap();
+    assert_eq!(name.as_deref(), Some("Japan"));
+    assert_eq!(
+        conn.qualified_table("countries").await.unwrap().as_deref(),
+        Some("\"reference\".\"countries\"")
+    );
+    assert_eq!(
+        conn.qualified_table("users").await.unwrap().as_deref(),
+        Some("\"main\".\"users\"")
+    );
+    assert_eq!(conn.qualified_table("missing").await.unwrap(), None);
+    // The reference database is read-only.
+    assert!(conn
+        .execute("INSERT INTO countries VALUES ('DE', 'Germany')", [])
+        .await
+        .is_err());
+    conn.close().await.unwrap();
+    let _ = std::fs::remove_file(&main_path);
+    let _ = std::fs::remove_file(&reference_path);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_column_decltypes() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute(
+        "CREATE TABLE test_tbl (a INTEGER PRIMARY KEY, b VARCHAR(10))",
+        [],
+    )
+    .await
+    .unwrap();
+    // Column types are available for queries without rows.
+    let rows = conn
+        .query("SELECT a, b, a + 1 FROM test_tbl", [])
+        .await
+        .unwrap();
+    assert_eq!(rows.column_count(), 3);
+    assert_eq!(rows.column_decltype(0), Some("INTEGER"));
+    assert_eq!(rows.column_decltype(1), Some("VARCHAR(10)"));
+    assert_eq!(rows.column_decltype(2), None);
+    assert_eq!(rows.column_decltype(3), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_table_row_counts() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch(
+        "CREATE TABLE a (id INTEGER PRIMARY KEY AUTOINCREMENT);
+        CREATE TABLE b (id INTEGER);
+        INSERT INTO a DEFAULT VALUES;
+        INSERT INTO a DEFAULT VALUES;
+        INSERT INTO b VALUES (1), (2), (3);",
+    )
+    .await
+    .unwrap();
+    let counts = conn.table_row_counts().await.unwrap();
+    // The sqlite_sequence table created by AUTOINCREMENT is skipped.
+    assert_eq!(counts.len(), 2);
+    assert_eq!(counts["a"], 2);
+    assert_eq!(counts["b"], 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_iter_arguments() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER, b INTEGER)", [])
+        .await
+        .unwrap();
+    let status = conn
+        .execute_iter(
+            "INSERT INTO test_tbl VALUES (?1, ?2)",
+            (1..=2).map(Value::Integer),
+        )
+        .await
+        .unwrap();
+    assert_eq!(status.rows_affected(), 1);
+    let mut rows = conn
+        .query_iter(
+            "SELECT a, b FROM test_tbl WHERE a = ?1",
+            std::iter::once(Value::Integer(1)),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        rows.next().await.unwrap().unwrap().values(),
+        vec![Value::Integer(1), Value::Integer(2)]
+    );
+    assert!(rows.next().await.is_none());
+    drop(rows);
+    // Parameter count mismatches are reported like for vectors.
+    assert!(conn
+        .execute_iter("INSERT INTO test_tbl VALUES (?1, ?2)", std::iter::empty())
+        .await
+        .is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_is_encrypted() {
+    let path = temp_path("is_encrypted");
+    assert!(!Connection::is_encrypted(&path).await.unwrap());
+    let mut conn = Connection::open(&path).await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    drop(conn);
+    assert!(!Connection::is_encrypted(&path).await.unwrap());
+    std::fs::write(&path, [0xA5; 1024]).unwrap();
+    assert!(Connection::is_encrypted(&path).await.unwrap());
+    // A file shorter than the header is not encrypted.
+    std::fs::write(&path, [0xA5; 8]).unwrap();
+    assert!(!Connection::is_encrypted(&path).await.unwrap());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_pool_acquire() {
+    let path = temp_path("pool_acquire");
+    let options = ConnectionOptions::new().journal_mode(JournalMode::Wal);
+    let pool = Pool::new(&path, options, 2);
+    let mut first = pool.acquire().await.unwrap();
+    first
+        .execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    let mut second = pool.acquire().await.unwrap();
+    // Both connections make progress while held at the same time.
+    let (a, b) = tokio::join!(
+        first.query_scalar::<i64, _, _>("SELECT COUNT(*) FROM test_tbl", []),
+        second.query_scalar::<i64, _, _>("SELECT COUNT(*) FROM test_tbl", []),
+    );
+    assert_eq!(a.unwrap(), Some(0));
+    assert_eq!(b.unwrap(), Some(0));
+    // The third acquire waits until a connection is released.
+    let pending = pool.clone();
+    let mut third = tokio::spawn(async move { pending.acquire().await.map(|_| ()) });
+    assert!(tokio::time::timeout(Duration::from_millis(100), &mut third)
+        .await
+        .is_err());
+    drop(first);
+    tokio::time::timeout(Duration::from_secs(5), third)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(pool.idle(), 1);
+    drop(second);
+    assert_eq!(pool.idle(), 2);
+    drop(pool);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(feature = "sqlcipher")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_open_encrypted() {
+    use tokio_sqlite::SecretKey;
+
+    let path = temp_path("open_encrypted");
+    let mut conn = Connection::open_encrypted(&path, SecretKey::passphrase("secret"))
+        .await
+        .unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    drop(conn);
+    assert!(Connection::is_encrypted(&path).await.unwrap());
+    assert!(
+        Connection::open_encrypted(&path, SecretKey::passphrase("wrong"))
+            .await
+            .is_err()
+    );
+    let mut conn = Connection::open_encrypted(&path, SecretKey::passphrase("secret"))
+        .await
+        .unwrap();
+    conn.execute("INSERT INTO test_tbl VALUES (1)", [])
+        .await
+        .unwrap();
+    drop(conn);
+    let _ = std::fs::remove_file(&path);
+    // Raw keys are used without derivation.
+    let path = temp_path("open_encrypted_raw");
+    let key = SecretKey::raw(&[7; 32]);
+    let mut conn = Connection::open_encrypted(&path, key.clone())
+        .await
+        .unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    drop(conn);
+    assert!(Connection::open_encrypted(&path, SecretKey::raw(&[8; 32]))
+        .await
+        .is_err());
+    Connection::open_encrypted(&path, key).await.unwrap();
+    let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(feature = "bb8")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_bb8_pool() {
+    use tokio_sqlite::SqliteConnectionManager;
+
+    let path = temp_path("bb8_pool");
+    let manager = SqliteConnectionManager::with_options(
+        &path,
+        ConnectionOptions::new().journal_mode(JournalMode::Wal),
+    );
+    let pool = bb8::Pool::builder()
+        .max_size(2)
+        .test_on_check_out(true)
+        .build(manager)
+        .await
+        .unwrap();
+    {
+        let mut conn = pool.get().await.unwrap();
+        conn.execute("CREATE TABLE test_tbl (a INTEGER)", [])
+            .await
+            .unwrap();
+        conn.execute("INSERT INTO test_tbl VALUES (42)", [])
+            .await
+            .unwrap();
+    }
+    let mut conn = pool.get().await.unwrap();
+    let value = conn
+        .query_scalar::<i64, _, _>("SELECT a FROM test_tbl", [])
+        .await
+        .unwrap();
+    assert_eq!(value, Some(42));
+    drop(conn);
+    drop(pool);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(feature = "sqlcipher")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_rekey() {
+    use tokio_sqlite::SecretKey;
+
+    let path = temp_path("rekey");
+    let mut conn = Connection::open_encrypted(&path, SecretKey::passphrase("old"))
+        .await
+        .unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    conn.rekey(SecretKey::passphrase("new")).await.unwrap();
+    drop(conn);
+    assert!(
+        Connection::open_encrypted(&path, SecretKey::passphrase("old"))
+            .await
+            .is_err()
+    );
+    let mut conn = Connection::open_encrypted(&path, SecretKey::passphrase("new"))
+        .await
+        .unwrap();
+    conn.execute("INSERT INTO test_tbl VALUES (1)", [])
+        .await
+        .unwrap();
+    drop(conn);
+    let _ = std::fs::remove_file(&path);
+    // Plaintext databases cannot be rekeyed.
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    assert!(matches!(
+        conn.rekey(SecretKey::passphrase("new")).await,
+        Err(Error::NotEncrypted)
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_connection_call() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch("PRAGMA user_version = 7").await.unwrap();
+    let version = conn
+        .call(|conn| Ok(conn.query_row("PRAGMA user_version", [], |row| row.get::<_, i32>(0))?))
+        .await
+        .unwrap();
+    assert_eq!(version, 7);
+    // A panic is reported without stopping the worker.
+    let result = conn.call(|_| -> Result<(), Error> { panic!("boom") }).await;
+    assert!(matches!(result, Err(Error::Panic { message }) if message == "boom"));
+    let version = conn
+        .call(|conn| Ok(conn.query_row("PRAGMA user_version", [], |row| row.get::<_, i32>(0))?))
+        .await
+        .unwrap();
+    assert_eq!(version, 7);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_query_with_rowid() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch(
+        "CREATE TABLE test_tbl (id INTEGER PRIMARY KEY, b TEXT);
+        INSERT INTO test_tbl VALUES (3, 'c'), (5, 'e'), (8, 'h');
+        CREATE TABLE test_nrt (id INTEGER PRIMARY KEY, b TEXT) WITHOUT ROWID;",
+    )
+    .await
+    .unwrap();
+    let rows: Vec<_> = conn
+        .query_with_rowid("test_tbl WHERE id > ?1 ORDER BY id", [Value::Integer(3)])
+        .await
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .await
+        .unwrap();
+    assert_eq!(rows.len(), 2);
+    for (rowid, row) in &rows {
+        assert_eq!(row.columns(), vec!["id", "b"]);
+        assert_eq!(row.get::<i64>(0).unwrap(), *rowid);
+    }
+    assert_eq!(rows[0].0, 5);
+    assert_eq!(rows[1].0, 8);
+    assert!(conn.query_with_rowid("test_nrt", []).await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_user_version() {
+    let path = temp_path("user_version");
+    let mut conn = Connection::open(&path).await.unwrap();
+    assert_eq!(conn.user_version().await.unwrap(), 0);
+    assert_eq!(conn.application_id().await.unwrap(), 0);
+    conn.set_user_version(-42).await.unwrap();
+    conn.set_application_id(0x0f0f0f0f).await.unwrap();
+    drop(conn);
+    let mut conn = Connection::open(&path).await.unwrap();
+    assert_eq!(conn.user_version().await.unwrap(), -42);
+    assert_eq!(conn.application_id().await.unwrap(), 0x0f0f0f0f);
+    conn.set_user_version(0).await.unwrap();
+    assert_eq!(conn.user_version().await.unwrap(), 0);
+    drop(conn);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_query_with_function() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    let rows = conn
+        .query_with_function(
+            "SELECT double_it(?1) AS v",
+            [Value::Integer(21)],
+            "double_it",
+            1,
+            |args| match args {
+                [Value::Integer(v)] => Ok(Value::Integer(v * 2)),
+                _ => Ok(Value::Null),
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].columns(), vec!["v"]);
+    assert_eq!(rows[0].get::<i64>(0).unwrap(), 42);
+    // The function is removed after the query.
+    assert!(conn.query_row("SELECT double_it(1)", []).await.is_err());
+    // Existing functions with the same arity are not replaced.
+    conn.create_scalar_function("triple_it", 1, |args| match args {
+        [Value::Integer(v)] => Ok(Value::Integer(v * 3)),
+        _ => Ok(Value::Null),
+    })
+    .await
+    .unwrap();
+    let err = conn
+        .query_with_function("SELECT 1", [], "TRIPLE_IT", 1, |_| Ok(Value::Null))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::FunctionAlreadyExists));
+    let err = conn
+        .query_with_function("SELECT 1", [], "upper", 1, |_| Ok(Value::Null))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::FunctionAlreadyExists));
+    let value: Option<i64> = conn.query_scalar("SELECT triple_it(2)", []).await.unwrap();
+    assert_eq!(value, Some(6));
+    // A different arity does not conflict.
+    let rows = conn
+        .query_with_function("SELECT triple_it(1, 2)", [], "triple_it", 2, |_| {
+            Ok(Value::Integer(0))
+        })
+        .await
+        .unwrap();
+    assert_eq!(rows[0].get::<i64>(0).unwrap(), 0);
+    let value: Option<i64> = conn.query_scalar("SELECT triple_it(2)", []).await.unwrap();
+    assert_eq!(value, Some(6));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_execute_with_retry_policy() {
+    let path = temp_path("execute_with_retry_policy");
+    let options = ConnectionOptions::new().busy_timeout(Duration::ZERO);
+    let mut holder = Connection::open_with_options(&path, options.clone())
+        .await
+        .unwrap();
+    holder
+        .execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    let mut conn = Connection::open_with_options(&path, options).await.unwrap();
+    let (locked_tx, locked_rx) = tokio::sync::oneshot::channel();
+    let task = tokio::spawn(async move {
+        let tx = holder
+            .transaction_with_behavior(TransactionBehavior::Exclusive)
+            .await
+            .unwrap();
+        locked_tx.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        tx.commit().await.unwrap();
+    });
+    locked_rx.await.unwrap();
+    let err = conn
+        .execute("INSERT INTO test_tbl VALUES (1)", [])
+        .await
+        .unwrap_err();
+    assert!(err.is_busy());
+    // Retries continue until the exclusive lock is released.
+    let policy = RetryPolicy::new(100, Duration::from_millis(10));
+    conn.execute_with_retry("INSERT INTO test_tbl VALUES (1)", [], policy)
+        .await
+        .unwrap();
+    task.await.unwrap();
+    // Errors other than busy are returned immediately.
+    let err = conn
+        .execute_with_retry("INSERT INTO missing_tbl VALUES (1)", [], policy)
+        .await
+        .unwrap_err();
+    assert!(!err.is_busy());
+    drop(conn);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_auto_commit_interval() {
+    let path = temp_path("auto_commit_interval");
+    let mut conn = Connection::open(&path).await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    let mut reader = Connection::open(&path).await.unwrap();
+    let mut tx = conn.transaction().await.unwrap();
+    tx.auto_commit_interval(Duration::from_millis(100));
+    tx.execute("INSERT INTO test_tbl VALUES (1)", &[])
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    // The write after the interval commits both rows.
+    tx.execute("INSERT INTO test_tbl VALUES (2)", &[])
+        .await
+        .unwrap();
+    let count = reader
+        .query_scalar::<i64, _, _>("SELECT COUNT(*) FROM test_tbl", [])
+        .await
+        .unwrap();
+    assert_eq!(count, Some(2));
+    tx.execute("INSERT INTO test_tbl VALUES (3)", &[])
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    tx.execute("INSERT INTO test_tbl VALUES (4)", &[])
+        .await
+        .unwrap();
+    tx.execute("INSERT INTO test_tbl VALUES (5)", &[])
+        .await
+        .unwrap();
+    // Only the write after the last commit is rolled back.
+    tx.rollback().await.unwrap();
+    let count = reader
+        .query_scalar::<i64, _, _>("SELECT COUNT(*) FROM test_tbl", [])
+        .await
+        .unwrap();
+    assert_eq!(count, Some(4));
+    drop(reader);
+    drop(conn);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_constraint_violation() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute(
+        "CREATE TABLE test_tbl (a INTEGER PRIMARY KEY, b TEXT UNIQUE)",
+        [],
+    )
+    .await
+    .unwrap();
+    conn.execute("INSERT INTO test_tbl VALUES (1, 'x')", [])
+        .await
+        .unwrap();
+    let err = conn
+        .execute("INSERT INTO test_tbl VALUES (1, 'y')", [])
+        .await
+        .unwrap_err();
+    assert!(err.is_constraint_violation());
+    assert!(!err.is_busy());
+    assert_eq!(
+        err.extended_code(),
+        Some(rusqlite::ffi::SQLITE_CONSTRAINT_PRIMARYKEY)
+    );
+    // Errors are passed through transactions untouched.
+    let mut tx = conn.transaction().await.unwrap();
+    let err = tx
+        .execute("INSERT INTO test_tbl VALUES (2, 'x')", &[])
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.extended_code(),
+        Some(rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE)
+    );
+    let err = tx.execute("SELEC 1", &[]).await.unwrap_err();
+    assert!(!err.is_constraint_violation());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_statement_reset() {
+    const INSERT: &str = "INSERT INTO test_tbl VALUES (?1)";
+
+    async fn run_count(conn: &mut Connection) -> i32 {
+        conn.call(|conn| {
+            let stmt = conn.prepare_cached(INSERT)?;
+            Ok(stmt.get_status(rusqlite::StatementStatus::Run))
+        })
+        .await
+        .unwrap()
+    }
+
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    let mut stmt = conn.prepare(INSERT).await.unwrap();
+    stmt.execute([Value::Integer(1)]).await.unwrap();
+    stmt.reset().await.unwrap();
+    stmt.execute([Value::Integer(2)]).await.unwrap();
+    stmt.reset().await.unwrap();
+    // Bindings are cleared, so missing parameters are still reported.
+    assert!(stmt.execute([]).await.is_err());
+    drop(stmt);
+    // Both executions used the same prepared statement.
+    assert_eq!(run_count(&mut conn).await, 2);
+    let stmt = conn.prepare(INSERT).await.unwrap();
+    stmt.finalize().await.unwrap();
+    // The finalized statement is prepared again.
+    assert_eq!(run_count(&mut conn).await, 0);
+    let rows = conn
+        .query_all("SELECT a FROM test_tbl ORDER BY a", [])
+        .await
+        .unwrap();
+    let values: Vec<i64> = rows.iter().map(|row| row.get(0).unwrap()).collect();
+    assert_eq!(values, vec![1, 2]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_shared_cache_memory_uri() {
+    let path = format!(
+        "file:tokio-sqlite-{}-shared?mode=memory&cache=shared",
+        std::process::id()
+    );
+    let mut first = Connection::builder()
+        .path(&path)
+        .uri(true)
+        .open()
+        .await
+        .unwrap();
+    first
+        .execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    first
+        .execute("INSERT INTO test_tbl VALUES (1)", [])
+        .await
+        .unwrap();
+    let mut second = Connection::builder()
+        .path(&path)
+        .uri(true)
+        .open()
+        .await
+        .unwrap();
+    let value = second
+        .query_scalar::<i64, _, _>("SELECT a FROM test_tbl", [])
+        .await
+        .unwrap();
+    assert_eq!(value, Some(1));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_no_such_table() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    let err = conn
+        .query_row("SELECT * FROM missing_tbl", [])
+        .await
+        .unwrap_err();
+    assert_eq!(err.no_such_table(), Some("missing_tbl"));
+    conn.execute("CREATE TABLE test_tbl (a INTEGER)", [])
+        .await
+        .unwrap();
+    let err = conn
+        .query_row("SELECT missing FROM test_tbl", [])
+        .await
+        .unwrap_err();
+    assert_eq!(err.no_such_table(), None);
+}
+
+#[cfg(feature = "hooks")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_progress_handler() {
+    const QUERY: &str = "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c) \
+        SELECT COUNT(*) FROM (SELECT x FROM c LIMIT 100000000)";
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    let handler_calls = calls.clone();
+    conn.progress_handler(
+        1000,
+        Some(move || handler_calls.fetch_add(1, Ordering::Relaxed) >= 10),
+    )
+    .await
+    .unwrap();
+    let err = conn.query_row(QUERY, []).await.unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Sqlite(ref err) if err.sqlite_error_code() == Some(rusqlite::ErrorCode::OperationInterrupted)
+    ));
+    assert_eq!(calls.load(Ordering::Relaxed), 11);
+    conn.progress_handler(0, None::<fn() -> bool>)
+        .await
+        .unwrap();
+    let value = conn
+        .query_scalar::<i64, _, _>("SELECT 1", [])
+        .await
+        .unwrap();
+    assert_eq!(value, Some(1));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_query_page() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute_batch(
+        "CREATE TABLE test_tbl (a INTEGER, b TEXT);
+        INSERT INTO test_tbl VALUES (1, 'x'), (2, 'limit'), (3, 'x'), (4, 'x'), (5, 'x');",
+    )
+    .await
+    .unwrap();
+    let page = |rows: Vec<Row>| -> Vec<i64> { rows.iter().map(|v| v.get(0).unwrap()).collect() };
+    let rows = conn
+        .query_page("SELECT a FROM test_tbl ORDER BY a DESC;", [], 2, 1)
+        .await
+        .unwrap();
+    assert_eq!(page(rows), vec![4, 3]);
+    // Arguments of the query are bound before the page bounds.
+    let rows = conn
+        .query_page(
+            "SELECT a FROM test_tbl WHERE a > ?1 AND b <> 'limit' ORDER BY a",
+            [Value::Integer(1)],
+            10,
+            1,
+        )
+        .await
+        .unwrap();
+    assert_eq!(page(rows), vec![4, 5]);
+    let err = conn
+        .query_page("SELECT a FROM test_tbl LIMIT 3", [], 2, 0)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::LimitConflict));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_rows_status() {
+    let mut conn = Connection::open(":memory:").await.unwrap();
+    conn.execute("CREATE TABLE test_tbl (id INTEGER PRIMARY KEY, b TEXT)", [])
+        .await
+        .unwrap();
+    let mut rows = conn
+        .query(
+            "INSERT INTO test_tbl (b) VALUES ('x'), ('y') RETURNING id",
+            [],
+        )
+        .await
+        .unwrap();
+    assert!(rows.status().is_none());
+    let mut ids = Vec::new();
+    while let Some(row) = rows.next().await {
+        ids.push(row.unwrap().get::<i64>(0).unwrap());
+    }
+    assert_eq!(ids, vec![1, 2]);
+    let status = rows.status().unwrap();
+    assert_eq!(status.rows_affected(), 2);
+    assert_eq!(status.last_insert_id(), Some(2));
+    drop(rows);
+    // Readonly queries do not report the counters of the earlier insert.
+    let mut rows = conn.query("SELECT id FROM test_tbl", []).await.unwrap();
+    while let Some(row) = rows.next().await {
+        row.unwrap();
+    }
+    let status = rows.status().unwrap();
+    assert_eq!(status.rows_affected(), 0);
+    assert_eq!(status.last_insert_id(), None);
+}
@@ -0,0 +1,226 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Attribute, Data, DataEnum, DeriveInput, Fields, LitStr};
+
+/// Derives `FromRow` for a struct with named fields.
+///
+/// Every field is read from the column with the same name. The column name
+/// can be changed with the `#[sqlite(rename = "...")]` attribute.
+#[proc_macro_derive(FromRow, attributes(sqlite))]
+pub fn derive_from_row(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match from_row(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn from_row(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    input,
+                    "FromRow can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "FromRow can only be derived for structs",
+            ))
+        }
+    };
+    let mut values = Vec::with_capacity(fields.len());
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap();
+        let name = ident.to_string();
+        let column = rename(&field.attrs)?.unwrap_or_else(|| name.clone());
+        values.push(quote! {
+            #ident: row.get_by_name(#column).map_err(|err| ::tokio_sqlite::Error::Field {
+                name: #name,
+                source: ::std::boxed::Box::new(err),
+            })?
+        });
+    }
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::tokio_sqlite::FromRow for #ident #ty_generics #where_clause {
+            fn from_row(
+                row: &::tokio_sqlite::Row,
+            ) -> ::std::result::Result<Self, ::tokio_sqlite::Error> {
+                ::std::result::Result::Ok(Self {
+                    #(#values,)*
+                })
+            }
+        }
+    })
+}
+
+/// Derives conversion of a fieldless enum into `Value`.
+///
+/// Variants are stored as integer discriminants. With the `#[sqlite(text)]`
+/// attribute on the enum they are stored as variant names instead, which
+/// can be changed with the `#[sqlite(rename = "...")]` attribute.
+#[proc_macro_derive(ToValue, attributes(sqlite))]
+pub fn derive_to_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match to_value(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Derives `FromValue` for a fieldless enum.
+///
+/// Accepts the same attributes as `ToValue`.
+#[proc_macro_derive(FromValue, attributes(sqlite))]
+pub fn derive_from_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match from_value(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn to_value(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let data = fieldless_enum(input)?;
+    let ident = &input.ident;
+    let value = if is_text(input)? {
+        let mut arms = Vec::with_capacity(data.variants.len());
+        for variant in &data.variants {
+            let name = variant_name(variant)?;
+            let variant = &variant.ident;
+            arms.push(quote! { #ident::#variant => #name });
+        }
+        quote! {
+            ::tokio_sqlite::Value::Text(::std::string::String::from(match value {
+                #(#arms,)*
+            }))
+        }
+    } else {
+        quote! { ::tokio_sqlite::Value::Integer(value as i64) }
+    };
+    Ok(quote! {
+        impl ::std::convert::From<#ident> for ::tokio_sqlite::Value {
+            fn from(value: #ident) -> Self {
+                #value
+            }
+        }
+    })
+}
+
+fn from_value(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let data = fieldless_enum(input)?;
+    let ident = &input.ident;
+    let mut arms = Vec::with_capacity(data.variants.len());
+    let body = if is_text(input)? {
+        for variant in &data.variants {
+            let name = variant_name(variant)?;
+            let variant = &variant.ident;
+            arms.push(quote! { #name => ::std::result::Result::Ok(Self::#variant) });
+        }
+        quote! {
+            match value {
+                ::tokio_sqlite::Value::Text(v) => match v.as_str() {
+                    #(#arms,)*
+                    v => ::std::result::Result::Err(::tokio_sqlite::FromSqlError::Other(
+                        ::std::format!("unknown variant `{v}`").into(),
+                    )),
+                },
+                _ => ::std::result::Result::Err(::tokio_sqlite::FromSqlError::InvalidType),
+            }
+        }
+    } else {
+        for variant in &data.variants {
+            let variant = &variant.ident;
+            arms.push(quote! {
+                v if v == Self::#variant as i64 => ::std::result::Result::Ok(Self::#variant)
+            });
+        }
+        quote! {
+            match value {
+                ::tokio_sqlite::Value::Integer(v) => match *v {
+                    #(#arms,)*
+                    v => ::std::result::Result::Err(::tokio_sqlite::FromSqlError::OutOfRange(v)),
+                },
+                _ => ::std::result::Result::Err(::tokio_sqlite::FromSqlError::InvalidType),
+            }
+        }
+    };
+    Ok(quote! {
+        impl ::tokio_sqlite::FromValue for #ident {
+            fn from_value(
+                value: &::tokio_sqlite::Value,
+            ) -> ::tokio_sqlite::FromSqlResult<Self> {
+                #body
+            }
+        }
+    })
+}
+
+fn fieldless_enum(input: &DeriveInput) -> syn::Result<&DataEnum> {
+    let Data::Enum(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            input,
+            "value conversions can only be derived for enums",
+        ));
+    };
+    for variant in &data.variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(syn::Error::new_spanned(
+                variant,
+                "value conversions can only be derived for enums without fields",
+            ));
+        }
+    }
+    Ok(data)
+}
+
+/// Returns the stored name of the variant for the text representation.
+fn variant_name(variant: &syn::Variant) -> syn::Result<String> {
+    Ok(rename(&variant.attrs)?.unwrap_or_else(|| variant.ident.to_string()))
+}
+
+/// Returns true if the enum has the `#[sqlite(text)]` attribute.
+fn is_text(input: &DeriveInput) -> syn::Result<bool> {
+    let mut text = false;
+    for attr in &input.attrs {
+        if !attr.path().is_ident("sqlite") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("text") {
+                text = true;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported sqlite attribute"))
+            }
+        })?;
+    }
+    Ok(text)
+}
+
+/// Returns the name set by the `#[sqlite(rename = "...")]` attribute.
+fn rename(attrs: &[Attribute]) -> syn::Result<Option<String>> {
+    let mut name = None;
+    for attr in attrs {
+        if !attr.path().is_ident("sqlite") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                let value: LitStr = meta.value()?.parse()?;
+                name = Some(value.value());
+                Ok(())
+            } else {
+                Err(meta.error("unsupported sqlite attribute"))
+            }
+        })?;
+    }
+    Ok(name)
+}